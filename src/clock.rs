@@ -9,6 +9,12 @@ pub struct Clock {
     phasor: Phasor,
 }
 
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Clock {
     pub fn new() -> Self {
         Clock {
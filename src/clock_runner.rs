@@ -1,135 +1,331 @@
 // module with the function that runs the clock thread
 
-use crate::clock::Clock;
 use crate::messages::*;
-use crate::midi_helpers::*;
+use crate::midi_helpers::MidiError;
+use crate::midi_ports::{MidiIn, MidiOut};
 use crate::note_assigner::Note;
 use crate::rho::Rho;
-use crate::rho_config::NUM_ROWS;
-use midir::MidiOutputConnection;
+use crate::rho_config::{MAX_TEMPO, MIN_TEMPO, PULSES_PER_STEP};
+use crate::scheduler::Scheduler;
+use crate::tap_tempo::TapTempo;
+use crate::tempo_estimator::TempoEstimator;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+// sleeping isn't accurate enough on its own, so stop sleeping this long before a pulse and spin
+const SPIN_TIME: Duration = Duration::from_micros(200);
+// at fast tempos the spin is limited to this fraction of a pulse, so it doesn't take over a core
+const MAX_SPIN_FRACTION: u32 = 16;
+// the longest we wait for midi in before checking for messages from the gui
+const MAX_WAIT: Duration = Duration::from_millis(5);
+// how many pulses to measure the timing jitter over before logging it
+const JITTER_LOG_PULSES: u32 = 16 * PULSES_PER_STEP as u32;
+
+fn spin_time(scheduler: &Scheduler) -> Duration {
+    SPIN_TIME.min(scheduler.pulse_duration() / MAX_SPIN_FRACTION)
+}
+
 pub fn run_clock(
     tx: std::sync::mpsc::Sender<MessageToGui>,
     running: Arc<AtomicBool>,
-    rx_midi_in: std::sync::mpsc::Receiver<MidiInMessage>,
     rx_gui: std::sync::mpsc::Receiver<MessageGuiToRho>,
 ) -> thread::JoinHandle<()> {
     let mut rho = Rho::new();
 
-    // is this ARC necessary?
-    let clock_arc = Arc::new(Mutex::new(Clock::new()));
-    // this is stupid, it only works if we have audio clock rate to sync to
-    // the clock should just be at whatever the tempo is
-    let sample_rate = 1000.0;
-    let period_ms = (1000.0 / sample_rate) as u64;
+    // works out when each midi clock pulse should happen, steps happen every PULSES_PER_STEP
+    // pulses
+    let mut scheduler = Scheduler::new(Instant::now());
 
-    let mut sent_notes_for_rows: [Vec<Note>; NUM_ROWS] = Default::default();
-    let mut midi_out_channel: u8 = 0;
+    let mut sent_notes_for_rows: Vec<Vec<Note>> = vec![];
+    let mut sent_playing_steps: Vec<Option<usize>> = vec![];
 
-    let mut maybe_midi_out_conn: Option<MidiOutputConnection> = None;
+    // the gui starts with the first output port selected
+    let mut midi_out = MidiOut::new();
+    if let Err(error) = midi_out.connect(0) {
+        report_midi_error(&tx, error);
+    }
+
+    // the gui starts with the first input port selected
+    let (tx_midi_in, rx_midi_in) = mpsc::channel();
+    let mut midi_in = MidiIn::new(tx_midi_in);
+    if let Err(error) = midi_in.connect(0) {
+        report_midi_error(&tx, error);
+    }
 
     let mut is_playing = false;
 
-    let mut tick_time = Instant::now();
+    // when external sync is on, the clock pulses and transport come from midi in instead
+    let mut external_sync = false;
+    let mut external_playing = false;
+    let mut tempo_estimator = TempoEstimator::new();
+
+    let mut tap_tempo = TapTempo::new();
+    let mut tap_cc: Option<u8> = None;
 
     // run a clock in another thread.
-    let handle = thread::spawn(move || {
+    thread::spawn(move || {
         while running.load(Ordering::SeqCst) {
-            // wait until the next clock tick
-            while Instant::now() < tick_time {
-                thread::yield_now();
+            // sleep until just before the next pulse, waking up early if midi comes in
+            let now = Instant::now();
+            let mut wait = MAX_WAIT;
+            if is_playing && !external_sync {
+                let wake_time = scheduler
+                    .next_pulse_time()
+                    .checked_sub(spin_time(&scheduler));
+                wait = wait.min(wake_time.map_or(Duration::ZERO, |t| t - now.min(t)));
             }
+            let first_message = rx_midi_in.recv_timeout(wait).ok();
 
-            // check to see if there are any messages from the midi in
-            match rx_midi_in.try_recv() {
-                Ok(MidiInMessage::NoteOn(note, velocity)) => {
-                    rho.note_on(note.into(), velocity.into());
-                }
-                Ok(MidiInMessage::NoteOff(note)) => {
-                    rho.note_off(note.into());
+            // handle all the messages from the midi in, clock messages can come thick and fast
+            for message in first_message.into_iter().chain(rx_midi_in.try_iter()) {
+                match message {
+                    MidiInMessage::NoteOn(channel, note, velocity) => {
+                        if midi_in.listens_to(channel) {
+                            rho.note_on(note.into(), velocity.into());
+                        }
+                    }
+                    MidiInMessage::NoteOff(channel, note) => {
+                        if midi_in.listens_to(channel) {
+                            rho.note_off(note.into());
+                        }
+                    }
+                    MidiInMessage::ControlChange(channel, cc, value) => {
+                        if !midi_in.listens_to(channel) {
+                            continue;
+                        }
+                        // only the press of a tap button counts, not the release
+                        if tap_cc == Some(cc) && value > 0 && !external_sync {
+                            on_tap(&mut tap_tempo, &mut scheduler, &tx, Instant::now());
+                        }
+                    }
+                    _ if !external_sync => (),
+                    MidiInMessage::TimingClock(stamp) => {
+                        if let Some(bpm) = tempo_estimator.on_pulse(stamp) {
+                            // once per step is often enough to update the display
+                            if rho.get_pulse_position() % PULSES_PER_STEP == 0 {
+                                let _ = tx.send(MessageToGui::ExternalTempo { bpm });
+                            }
+                        }
+                        if external_playing {
+                            on_clock_pulse(&mut rho, &mut midi_out);
+                        }
+                    }
+                    MidiInMessage::Start => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        rho.reset();
+                        midi_out.send_clock_message(START_MSG);
+                        external_playing = true;
+                    }
+                    MidiInMessage::Continue => {
+                        midi_out.send_clock_message(CONTINUE_MSG);
+                        external_playing = true;
+                    }
+                    MidiInMessage::Stop => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        midi_out.send_clock_message(STOP_MSG);
+                        external_playing = false;
+                        tempo_estimator.reset();
+                    }
+                    MidiInMessage::SongPosition(midi_beats) => {
+                        // a midi beat is a sixteenth note, which is 6 midi clock pulses
+                        rho.set_pulse_position(midi_beats as usize * 6);
+                    }
                 }
-                _ => (),
             }
 
-            match rx_gui.try_recv() {
-                Ok(MessageGuiToRho::RowActivations { row_activations }) => {
-                    rho.set_row_activations(row_activations);
-                }
-                Ok(MessageGuiToRho::HoldNotesEnabled { enabled }) => {
-                    rho.set_hold_notes_enabled(enabled);
-                }
-                Ok(MessageGuiToRho::SetMidiOutPort { port }) => {
-                    // open a midi out connection
-                    let midi_out_conn = get_midi_out_connection(port);
-                    maybe_midi_out_conn = match midi_out_conn {
-                        Ok(conn) => Some(conn),
-                        Err(e) => {
-                            eprintln!("Error: {}", e);
-                            return;
+            for message in rx_gui.try_iter() {
+                match message {
+                    MessageGuiToRho::RowActivations { row_activations } => {
+                        rho.set_row_activations(row_activations);
+                    }
+                    MessageGuiToRho::SetNumRows { num_rows } => {
+                        // the notes of removed rows would never be stopped
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        rho.set_num_rows(num_rows);
+                        midi_out.set_num_rows(rho.num_rows());
+                    }
+                    MessageGuiToRho::SetNoteOrdering { ordering } => {
+                        rho.set_note_ordering(ordering);
+                    }
+                    MessageGuiToRho::SetRowAssign { mode } => {
+                        rho.set_row_assign(mode);
+                    }
+                    MessageGuiToRho::SetNoteWrapping { wrapping } => {
+                        rho.set_note_wrapping(wrapping);
+                    }
+                    MessageGuiToRho::SetInvertRows { enabled } => {
+                        rho.set_invert_rows_enabled(enabled);
+                    }
+                    MessageGuiToRho::SetOctaveFill {
+                        enabled,
+                        direction,
+                        max_octaves,
+                    } => {
+                        rho.set_octave_fill(enabled, direction, max_octaves);
+                    }
+                    MessageGuiToRho::SetMidiOutPort { port } => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        if let Err(error) = midi_out.connect(port) {
+                            report_midi_error(&tx, error);
                         }
-                    };
-                }
-                Ok(MessageGuiToRho::SetMidiChannelOut { channel }) => {
-                    midi_out_channel = channel;
-                }
-                Ok(MessageGuiToRho::SetPlaying { playing }) => {
-                    is_playing = playing;
-                }
-                Ok(MessageGuiToRho::SetTempo { tempo }) => {
-                    let mut clock = clock_arc.lock().unwrap();
-                    let rate_hz = tempo / 60.0;
-                    clock.set_rate(rate_hz, sample_rate);
+                    }
+                    // the held notes' note offs won't be heard any more, so let go of them
+                    MessageGuiToRho::SetMidiInPort { port } => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        rho.release_all_notes();
+                        if let Err(error) = midi_in.connect(port) {
+                            report_midi_error(&tx, error);
+                        }
+                    }
+                    MessageGuiToRho::SetMidiChannelIn { channel } => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        rho.release_all_notes();
+                        midi_in.channel = channel;
+                    }
+                    MessageGuiToRho::SetMidiClockOut { port, enabled } => {
+                        if enabled {
+                            midi_out.clock_ports.insert(port);
+                        } else {
+                            midi_out.clock_ports.remove(&port);
+                        }
+                    }
+                    MessageGuiToRho::SetMidiChannelOut { channel } => {
+                        // the playing notes would get their note offs on the new channel
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        midi_out.channel = channel;
+                    }
+                    MessageGuiToRho::SetRowRoute { row, route } => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        if let Err(error) = midi_out.set_row_route(row, route) {
+                            report_midi_error(&tx, error);
+                        }
+                    }
+                    MessageGuiToRho::SetPlaying { playing } => {
+                        if playing != is_playing && !external_sync {
+                            midi_out.send_clock_message(transport_message(
+                                playing,
+                                rho.get_pulse_position(),
+                            ));
+                        }
+                        if playing && !is_playing {
+                            scheduler.start(Instant::now());
+                        }
+                        if !playing {
+                            stop_playing_notes(&mut rho, &mut midi_out);
+                        }
+                        is_playing = playing;
+                    }
+                    MessageGuiToRho::Rewind => {
+                        if !external_sync {
+                            stop_playing_notes(&mut rho, &mut midi_out);
+                            rho.reset();
+                            // while playing it carries on from the top straight away
+                            if is_playing {
+                                midi_out.send_clock_message(START_MSG);
+                                scheduler.start(Instant::now());
+                            }
+                        }
+                    }
+                    MessageGuiToRho::SetTempo { tempo } => {
+                        scheduler.set_tempo(tempo);
+                    }
+                    MessageGuiToRho::SetRowRate { row, rate } => {
+                        rho.set_row_rate(row, rate);
+                    }
+                    MessageGuiToRho::SetSwing { swing } => {
+                        rho.set_swing(swing);
+                    }
+                    MessageGuiToRho::SetGroove { groove } => {
+                        rho.set_groove(groove);
+                    }
+                    MessageGuiToRho::SetGateLength { length } => {
+                        rho.set_gate_length(length);
+                    }
+                    MessageGuiToRho::SetRowGateLength { row, length } => {
+                        rho.set_row_gate_length(row, length);
+                    }
+                    MessageGuiToRho::SetVelocityCurve { curve } => {
+                        rho.set_velocity_curve(curve);
+                    }
+                    MessageGuiToRho::SetFixedVelocity { velocity } => {
+                        rho.set_fixed_velocity(velocity);
+                    }
+                    MessageGuiToRho::SetRowVelocity { row, velocity } => {
+                        rho.set_row_velocity(row, velocity);
+                    }
+                    MessageGuiToRho::TapTempo { time } => {
+                        if !external_sync {
+                            on_tap(&mut tap_tempo, &mut scheduler, &tx, time);
+                        }
+                    }
+                    MessageGuiToRho::SetTapCc { cc } => {
+                        tap_cc = cc;
+                    }
+                    MessageGuiToRho::SetNudge { nudge } => {
+                        scheduler.set_nudge(nudge);
+                    }
+                    MessageGuiToRho::SetRowPitch { row, pitch } => {
+                        rho.set_row_pitch(row, pitch);
+                    }
+                    MessageGuiToRho::SetScale { scale } => {
+                        rho.set_scale(scale);
+                    }
+                    MessageGuiToRho::SetFill { enabled } => {
+                        rho.set_fill(enabled);
+                    }
+                    MessageGuiToRho::Panic => {
+                        rho.stop_all_notes();
+                        midi_out.panic();
+                    }
+                    MessageGuiToRho::SetPanicCcs { enabled } => {
+                        midi_out.send_panic_ccs = enabled;
+                    }
+                    MessageGuiToRho::SetExternalSync { enabled } => {
+                        stop_playing_notes(&mut rho, &mut midi_out);
+                        external_sync = enabled;
+                        external_playing = false;
+                        tempo_estimator.reset();
+                    }
                 }
-                _ => (),
             }
 
-            let mut clock = clock_arc.lock().unwrap();
-
-            if is_playing {
-                let clock_out = clock.tick();
-                if let Some(c) = clock_out {
-                    if c {
-                        // now get the notes to play
-                        let notes_to_play = rho.on_clock_high();
-
-                        for note in notes_to_play {
-                            print!("----------clock------------- OUTPUT note on {}\n", note);
-                            // send midi note on
-                            let midi_out_conn = maybe_midi_out_conn.as_mut().unwrap();
-                            midi_out_conn
-                                .send(&[
-                                    NOTE_ON_MSG + midi_out_channel,
-                                    note.note_number as u8,
-                                    0x64,
-                                ])
-                                .unwrap();
-                        }
-                        tx.send(MessageToGui::Tick {
-                            playing_steps: rho.get_playing_steps(),
-                        })
-                        .unwrap();
-                    } else {
-                        // send midi off for all notes
-                        let notes_to_stop = rho.on_clock_low();
-                        for note in notes_to_stop {
-                            print!("----------clock------------- OUTPUT note off {}\n", note);
-                            // send midi note off
-                            // TODO this can panic!
-                            let midi_out_conn = maybe_midi_out_conn.as_mut().unwrap();
-                            midi_out_conn
-                                .send(&[
-                                    NOTE_OFF_MSG + midi_out_channel,
-                                    note.note_number as u8,
-                                    0x64,
-                                ])
-                                .unwrap();
-                        }
+            if is_playing && !external_sync {
+                let pulse_time = scheduler.next_pulse_time();
+                if pulse_time <= Instant::now() + spin_time(&scheduler) {
+                    while Instant::now() < pulse_time {
+                        thread::yield_now();
+                    }
+                    scheduler.on_pulse(Instant::now());
+                    on_clock_pulse(&mut rho, &mut midi_out);
+
+                    let jitter = scheduler.jitter();
+                    if jitter.count() >= JITTER_LOG_PULSES {
+                        log::debug!(
+                            "clock jitter over {} pulses: mean {:?}, max {:?}",
+                            jitter.count(),
+                            jitter.mean(),
+                            jitter.max()
+                        );
+                        let _ = tx.send(MessageToGui::ClockJitter { jitter });
+                        scheduler.reset_jitter();
                     }
                 }
+            }
+
+            // a failed send doesn't stop anything, the gui shows it so another port can be chosen
+            for error in midi_out.take_errors() {
+                report_midi_error(&tx, error);
+            }
+
+            if is_playing || external_playing {
+                let playing_steps = rho.get_playing_steps();
+                if playing_steps != sent_playing_steps {
+                    sent_playing_steps = playing_steps.clone();
+                    let _ = tx.send(MessageToGui::Tick { playing_steps });
+                }
 
                 let new_notes_for_rows = rho.get_notes_for_rows();
                 if new_notes_for_rows != sent_notes_for_rows {
@@ -139,13 +335,86 @@ pub fn run_clock(
                     });
                 }
             }
-
-            // work out when next clock tick is
-            let accuracy = Duration::from_millis(1);
-            tick_time += Duration::from_millis(period_ms);
-            thread::park_timeout(tick_time - accuracy - Instant::now());
         }
-    });
-    // TODO stop playing midi notes!
-    handle
+
+        // don't leave notes hanging when the app closes
+        stop_playing_notes(&mut rho, &mut midi_out);
+    })
+}
+
+// handle a single midi clock pulse, whether it came from the internal clock or from midi in
+fn on_clock_pulse(rho: &mut Rho, midi_out: &mut MidiOut) {
+    midi_out.send_clock_message(TIMING_CLOCK_MSG);
+
+    // stop notes before starting new ones, in case the same note is played again
+    let pulse_notes = rho.on_clock_pulse();
+    for (row, note) in pulse_notes.note_offs {
+        midi_out.send_note_off(row, &note);
+    }
+    for (row, note) in pulse_notes.note_ons {
+        midi_out.send_note_on(row, &note);
+    }
+}
+
+// the midi message for the transport starting or stopping. Playing from the very beginning is a
+// start, from anywhere else it's a continue
+fn transport_message(playing: bool, pulse_position: usize) -> u8 {
+    if !playing {
+        STOP_MSG
+    } else if pulse_position == 0 {
+        START_MSG
+    } else {
+        CONTINUE_MSG
+    }
+}
+
+// work out the tempo from the taps so far, and let the gui know about it
+fn on_tap(
+    tap_tempo: &mut TapTempo,
+    scheduler: &mut Scheduler,
+    tx: &std::sync::mpsc::Sender<MessageToGui>,
+    time: Instant,
+) {
+    if let Some(bpm) = tap_tempo.on_tap(time) {
+        let bpm = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        scheduler.set_tempo(bpm);
+        let _ = tx.send(MessageToGui::Tempo { bpm });
+    }
+}
+
+fn report_midi_error(tx: &std::sync::mpsc::Sender<MessageToGui>, error: MidiError) {
+    eprintln!("Error: {}", error);
+    let _ = tx.send(MessageToGui::MidiError { error });
+}
+
+// stop the notes rho is playing, and anything else that was left sounding
+fn stop_playing_notes(rho: &mut Rho, midi_out: &mut MidiOut) {
+    let notes_to_stop = rho.stop_all_notes();
+    for (row, note) in notes_to_stop {
+        midi_out.send_note_off(row, &note);
+    }
+    midi_out.stop_all_notes();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_message() {
+        assert_eq!(transport_message(true, 0), START_MSG);
+        assert_eq!(transport_message(true, 96), CONTINUE_MSG);
+        assert_eq!(transport_message(false, 96), STOP_MSG);
+
+        // after a rewind the next play is a start again
+        let mut rho = Rho::new();
+        rho.on_clock_pulse();
+        rho.on_clock_pulse();
+        assert_eq!(
+            transport_message(true, rho.get_pulse_position()),
+            CONTINUE_MSG
+        );
+        rho.reset();
+        assert_eq!(transport_message(true, rho.get_pulse_position()), START_MSG);
+    }
 }
@@ -3,9 +3,13 @@
 extern crate rand;
 
 use crate::rand::prelude::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::cmp::Reverse;
+use std::error::Error;
 
-use crate::rho_config::NUM_ROWS;
+use crate::rho_config::{MAX_ROWS, MAX_ROW_LENGTH};
+use crate::step::{Step, StepParams};
 
 //--------------------------------------------------------------------------------
 // TODOs
@@ -39,16 +43,116 @@ pub fn unflatten(flat: &Vec<usize>, row_lengths: &Vec<usize>) -> Vec<Vec<usize>>
     grid
 }
 
-// create a new bunch of thresholds
-pub fn create_new_distribution(n: usize) -> Vec<usize> {
+// create a new bunch of thresholds, the same seed always gives the same thresholds
+pub fn create_new_distribution(n: usize, seed: u64) -> Vec<usize> {
     // vec has ascending integers 0-N
     // then shuffle it randomly
     let mut v = Vec::from_iter(0..n);
-    let mut rng = thread_rng();
+    let mut rng = StdRng::seed_from_u64(seed);
     v.shuffle(&mut rng);
     v
 }
 
+// the settings of a euclidean rhythm, hits spread as evenly as possible over the steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Euclidean {
+    pub hits: usize,
+    pub steps: usize,
+    pub rotation: usize, // how many steps later the pattern starts
+}
+
+impl Default for Euclidean {
+    fn default() -> Self {
+        Euclidean {
+            hits: 3,
+            steps: 8,
+            rotation: 0,
+        }
+    }
+}
+
+impl Euclidean {
+    pub fn pattern(&self) -> Vec<bool> {
+        let pattern = bjorklund(self.hits, self.steps);
+        let steps = pattern.len();
+        if steps == 0 {
+            return pattern;
+        }
+        let rotation = self.rotation % steps;
+        (0..steps)
+            .map(|i| pattern[(i + steps - rotation) % steps])
+            .collect()
+    }
+}
+
+// the bjorklund algorithm, the hits and rests are paired off into groups until they can't be
+// spread out any more evenly
+pub fn bjorklund(hits: usize, steps: usize) -> Vec<bool> {
+    if hits >= steps {
+        return vec![true; steps];
+    }
+    if hits == 0 {
+        return vec![false; steps];
+    }
+
+    let mut groups: Vec<Vec<bool>> = vec![vec![true]; hits];
+    let mut remainders: Vec<Vec<bool>> = vec![vec![false]; steps - hits];
+    while remainders.len() > 1 {
+        let paired = groups.len().min(remainders.len());
+        let new_remainders = if groups.len() > paired {
+            groups.split_off(paired)
+        } else {
+            remainders.split_off(paired)
+        };
+        for (group, remainder) in groups.iter_mut().zip(remainders.iter()) {
+            group.extend(remainder);
+        }
+        remainders = new_remainders;
+    }
+    groups.into_iter().chain(remainders).flatten().collect()
+}
+
+// the order the steps of a pattern should come in as the density goes up. The hits come first,
+// the most crowded ones last so they're the first to go, then the rests, the ones furthest from
+// any hit and in the biggest gap first
+pub fn euclidean_order(pattern: &[bool]) -> Vec<usize> {
+    let steps = pattern.len();
+    // the number of steps from one active step to the next, going round the end of the row
+    let gap_after = |active: &[bool], i: usize| {
+        (1..=steps)
+            .find(|d| active[(i + d) % steps])
+            .unwrap_or(steps)
+    };
+    let gap_before = |active: &[bool], i: usize| {
+        (1..=steps)
+            .find(|d| active[(i + steps - d) % steps])
+            .unwrap_or(steps)
+    };
+
+    let mut active = pattern.to_vec();
+    let mut removed = vec![];
+    while let Some(i) = (0..steps)
+        .filter(|&i| active[i])
+        .min_by_key(|&i| (gap_before(&active, i) + gap_after(&active, i), Reverse(i)))
+    {
+        active[i] = false;
+        removed.push(i);
+    }
+    removed.reverse();
+
+    let mut active = pattern.to_vec();
+    let mut added = vec![];
+    while let Some(i) = (0..steps).filter(|&i| !active[i]).max_by_key(|&i| {
+        let (before, after) = (gap_before(&active, i), gap_after(&active, i));
+        (before.min(after), before + after, Reverse(i))
+    }) {
+        active[i] = true;
+        added.push(i);
+    }
+
+    removed.into_iter().chain(added).collect()
+}
+
 pub fn flat_index_to_grid_index(flat_index: usize, row_lengths: &Vec<usize>) -> (usize, usize) {
     let mut row_index = 0;
     let mut step_index = 0;
@@ -79,36 +183,139 @@ pub fn grid_index_to_flat_index(grid_index: (usize, usize), row_lengths: &Vec<us
 
     // allow an index just off end
     debug_assert!(flat <= row_lengths.iter().sum());
-    return flat;
+    flat
 }
 
 pub struct GridActivations {
     active: Vec<bool>,
     thresh: Vec<usize>,
+    params: Vec<StepParams>,
     row_lengths: Vec<usize>,
     // these suck because they both interdepend on the steps
     normalized_density: f32,
+    // the seed of the last distribution, it's saved with the pattern. The same seed with the
+    // same active steps and row lengths gives the same thresholds
+    seed: u64,
 }
 
 impl GridActivations {
-    pub fn new(rows: usize, steps: usize) -> Self {
+    pub fn new(rows: usize, steps: usize, seed: u64) -> Self {
         let total_steps = steps * rows;
         GridActivations {
             active: vec![false; total_steps],
-            thresh: create_new_distribution(total_steps),
+            thresh: create_new_distribution(total_steps, seed),
+            params: vec![StepParams::default(); total_steps],
             row_lengths: vec![steps; rows],
             normalized_density: 0.0,
+            seed,
         }
     }
 
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // a pattern file has the seed on the first line e.g. "seed 42", then a line for each row with
+    // x for an active step and . for an inactive one, followed by the step thresholds
+    // e.g. "x..x 0 3 2 1". Step settings aren't saved
+    pub fn to_pattern_text(&self) -> String {
+        let mut text = format!("seed {}\n", self.seed);
+        let mut start = 0;
+        for &length in self.row_lengths.iter() {
+            let steps: String = self.active[start..start + length]
+                .iter()
+                .map(|&on| if on { 'x' } else { '.' })
+                .collect();
+            let thresh: Vec<String> = self.thresh[start..start + length]
+                .iter()
+                .map(|t| t.to_string())
+                .collect();
+            text.push_str(&format!("{} {}\n", steps, thresh.join(" ")));
+            start += length;
+        }
+        text
+    }
+
+    pub fn parse_pattern(text: &str) -> Result<GridActivations, Box<dyn Error>> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed "))
+            .ok_or("Missing seed")?
+            .trim()
+            .parse()?;
+
+        let mut grid = GridActivations {
+            active: vec![],
+            thresh: vec![],
+            params: vec![],
+            row_lengths: vec![],
+            normalized_density: 0.0,
+            seed,
+        };
+        for line in lines {
+            let mut values = line.split_whitespace();
+            let steps = values.next().ok_or("Missing steps")?;
+            for step in steps.chars() {
+                match step {
+                    'x' => grid.active.push(true),
+                    '.' => grid.active.push(false),
+                    _ => return Err(format!("Unknown step '{}'", step).into()),
+                }
+            }
+            for value in values {
+                grid.thresh.push(value.parse()?);
+            }
+            grid.row_lengths.push(steps.len());
+        }
+
+        if grid.row_lengths.is_empty() || grid.row_lengths.len() > MAX_ROWS {
+            return Err(format!("A pattern needs 1 to {} rows", MAX_ROWS).into());
+        }
+        if grid
+            .row_lengths
+            .iter()
+            .any(|&l| l == 0 || l > MAX_ROW_LENGTH)
+        {
+            return Err(format!("Rows need 1 to {} steps", MAX_ROW_LENGTH).into());
+        }
+        // each step has its own threshold, and the active steps have the lowest ones
+        let mut sorted_thresh = grid.thresh.clone();
+        sorted_thresh.sort();
+        let num_active_steps = grid.num_active_steps();
+        if sorted_thresh != Vec::from_iter(0..grid.active.len())
+            || (0..grid.active.len()).any(|i| grid.active[i] != (grid.thresh[i] < num_active_steps))
+        {
+            return Err("The thresholds don't match the steps".into());
+        }
+
+        grid.params = vec![StepParams::default(); grid.active.len()];
+        grid.update_density();
+        Ok(grid)
+    }
+
+    pub fn load(path: &str) -> Result<GridActivations, Box<dyn Error>> {
+        GridActivations::parse_pattern(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_pattern_text())?;
+        Ok(())
+    }
+
     pub fn get_total_num_steps(&self) -> usize {
         self.row_lengths.iter().sum()
     }
 
     pub fn set_normalized_density(&mut self, density: f32) {
-        print!("set_normalized_density {}\n", density);
+        println!("set_normalized_density {}", density);
         self.normalized_density = density;
-        let wanted_num_active_steps = (density * self.get_total_num_steps() as f32) as usize;
+        // rounded, otherwise a density worked out from a number of steps can come out one short
+        let wanted_num_active_steps =
+            (density * self.get_total_num_steps() as f32).round() as usize;
 
         if self.num_active_steps() != wanted_num_active_steps {
             self.set_activations_for_new_density(wanted_num_active_steps);
@@ -119,11 +326,18 @@ impl GridActivations {
         self.normalized_density
     }
 
+    // like setting the density, but exact however many steps there are
+    pub fn set_num_active_steps(&mut self, num_active_steps: usize) {
+        let num_active_steps = num_active_steps.min(self.get_total_num_steps());
+        self.set_activations_for_new_density(num_active_steps);
+        self.update_density();
+    }
+
     pub fn set_row_length(&mut self, row_index: usize, new_length: usize) {
-        if new_length > self.row_lengths[row_index] {
-            self.append_steps(row_index, new_length);
-        } else if new_length < self.row_lengths[row_index] {
-            self.remove_steps(row_index, new_length);
+        match new_length.cmp(&self.row_lengths[row_index]) {
+            std::cmp::Ordering::Greater => self.append_steps(row_index, new_length),
+            std::cmp::Ordering::Less => self.remove_steps(row_index, new_length),
+            std::cmp::Ordering::Equal => (),
         }
     }
 
@@ -140,20 +354,80 @@ impl GridActivations {
         self.active[start..end].to_vec()
     }
 
-    pub fn get_row_activations(&self) -> [Vec<bool>; NUM_ROWS] {
-        let mut result: [Vec<bool>; NUM_ROWS] = Default::default();
-        for i in 0..self.row_lengths.len() {
-            result[i] = self.get_row(i);
+    // the active state and params of every step in the row
+    pub fn get_row_steps(&self, index: usize) -> Vec<Step> {
+        let start = self.row_lengths[0..index].iter().sum();
+        let end = start + self.row_lengths[index];
+        self.active[start..end]
+            .iter()
+            .zip(self.params[start..end].iter())
+            .map(|(active, params)| Step {
+                active: *active,
+                params: *params,
+            })
+            .collect()
+    }
+
+    pub fn get_row_activations(&self) -> Vec<Vec<Step>> {
+        (0..self.row_lengths.len())
+            .map(|i| self.get_row_steps(i))
+            .collect()
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.row_lengths.len()
+    }
+
+    // rows are added and removed at the end, like steps are in a row, so the other rows keep
+    // their pattern and the order their steps come in as the density changes
+    pub fn set_num_rows(&mut self, num_rows: usize, steps: usize) {
+        while self.row_lengths.len() > num_rows {
+            let last_row = self.row_lengths.len() - 1;
+            if self.row_lengths[last_row] > 0 {
+                self.remove_steps(last_row, 0);
+            }
+            self.row_lengths.pop();
         }
-        result
+        while self.row_lengths.len() < num_rows {
+            self.row_lengths.push(0);
+            self.append_steps(self.row_lengths.len() - 1, steps);
+        }
+        self.update_density();
     }
 
-    fn num_active_steps(&self) -> usize {
+    pub fn num_active_steps(&self) -> usize {
         self.active
             .iter()
             .fold(0, |acc, x| if *x { acc + 1 } else { acc })
     }
 
+    pub fn row_num_active_steps(&self, row: usize) -> usize {
+        self.get_row(row).iter().filter(|x| **x).count()
+    }
+
+    // the density of a single row, the row's steps go on and off in the order of their
+    // thresholds and the other rows are left as they are
+    pub fn set_row_num_active_steps(&mut self, row: usize, num_active_steps: usize) {
+        let start = grid_index_to_flat_index((row, 0), &self.row_lengths);
+        let end = start + self.row_lengths[row];
+
+        // the row's steps from the lowest threshold up
+        let mut order: Vec<usize> = (start..end).collect();
+        order.sort_by_key(|&i| self.thresh[i]);
+        for (rank, &i) in order.iter().enumerate() {
+            self.change_step_update_thresholds(i, rank < num_active_steps);
+        }
+
+        // changing the steps swaps thresholds about, so share the row's thresholds back out in
+        // the order it had. The active steps still get the ones under the density
+        let mut row_thresh = self.thresh[start..end].to_vec();
+        row_thresh.sort();
+        for (i, thresh) in order.into_iter().zip(row_thresh) {
+            self.thresh[i] = thresh;
+        }
+        self.update_density();
+    }
+
     pub fn set(&mut self, row: usize, step: usize, on: bool) {
         let flat_index = grid_index_to_flat_index((row, step), &self.row_lengths);
         self.change_step_update_thresholds(flat_index, on);
@@ -188,20 +462,22 @@ impl GridActivations {
     // The threshold is returned as a density
     // the use case is, you have a nice sequence but you want a new way to randomise it.
     // it is conveneient to reuse change step but something about this function really sucks
-    pub fn create_new_distribution_given_active_steps(&mut self) {
+    pub fn create_new_distribution_given_active_steps(&mut self, seed: u64) {
         let original_active = self.active.clone();
         let n = self.active.len();
+        self.seed = seed;
 
         // set all to false
         self.active = vec![false; n];
 
         // randomise thresholds
-        self.thresh = create_new_distribution(n);
+        self.thresh = create_new_distribution(n, seed);
 
         // now make sure lowest thresholds correspond to active steps activating steps one by
         // one
-        // need to randomise order to avoid consecutive thresholds
-        let random_order = create_new_distribution(n);
+        // need to randomise order to avoid consecutive thresholds, it's from a different seed so
+        // that it isn't the same shuffle as the thresholds
+        let random_order = create_new_distribution(n, !seed);
 
         for i in random_order {
             self.change_step_update_thresholds(i, original_active[i]);
@@ -222,10 +498,13 @@ impl GridActivations {
         let mut thresh_to_insert: Vec<_> =
             (old_flat_length..old_flat_length + num_to_insert).collect();
 
-        let mut rng = thread_rng();
+        // seeded by how many steps there were before, so the same seed and the same edits give
+        // the same thresholds
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(old_flat_length as u64));
         thresh_to_insert.shuffle(&mut rng);
 
         let active_to_insert = vec![false; num_to_insert];
+        let params_to_insert = vec![StepParams::default(); num_to_insert];
 
         let insert_position = grid_index_to_flat_index((row_to_append + 1, 0), &self.row_lengths);
 
@@ -233,9 +512,12 @@ impl GridActivations {
             .splice(insert_position..insert_position, active_to_insert);
         self.thresh
             .splice(insert_position..insert_position, thresh_to_insert);
+        self.params
+            .splice(insert_position..insert_position, params_to_insert);
 
         // @todo is there some nice way to assert this always happens for any mutation
         debug_assert!(self.active.len() == self.thresh.len());
+        debug_assert!(self.active.len() == self.params.len());
 
         self.row_lengths[row_to_append] = new_length;
         self.update_density();
@@ -255,6 +537,7 @@ impl GridActivations {
             // erase the active step and the thresh at that point
             self.thresh.remove(remove_position);
             self.active.remove(remove_position);
+            self.params.remove(remove_position);
 
             // all the thresholds higher than the removed one need to be reduced by one
             self.thresh.iter_mut().for_each(|x| {
@@ -268,6 +551,31 @@ impl GridActivations {
         self.update_density();
     }
 
+    // write a euclidean pattern into the row, and order its thresholds so that changing the
+    // density adds and removes the row's steps evenly
+    pub fn set_euclidean(&mut self, row: usize, euclidean: Euclidean) {
+        let euclidean = Euclidean {
+            steps: euclidean.steps.max(1),
+            ..euclidean
+        };
+        self.set_row_length(row, euclidean.steps);
+        let pattern = euclidean.pattern();
+        let start = grid_index_to_flat_index((row, 0), &self.row_lengths);
+
+        for (i, on) in pattern.iter().enumerate() {
+            self.change_step_update_thresholds(start + i, *on);
+        }
+
+        // the row keeps the same thresholds, they're shared out between its steps in order.
+        // The hits have all the thresholds under the density so they stay active
+        let mut row_thresh = self.thresh[start..start + pattern.len()].to_vec();
+        row_thresh.sort();
+        for (step, thresh) in euclidean_order(&pattern).into_iter().zip(row_thresh) {
+            self.thresh[start + step] = thresh;
+        }
+        self.update_density();
+    }
+
     pub fn get_row_length(&self, row: usize) -> usize {
         self.row_lengths[row]
     }
@@ -278,6 +586,15 @@ impl GridActivations {
         self.active[grid_index_to_flat_index((row, step), &self.row_lengths)]
     }
 
+    pub fn get_params(&self, row: usize, step: usize) -> StepParams {
+        self.params[grid_index_to_flat_index((row, step), &self.row_lengths)]
+    }
+
+    pub fn set_params(&mut self, row: usize, step: usize, params: StepParams) {
+        let flat_index = grid_index_to_flat_index((row, step), &self.row_lengths);
+        self.params[flat_index] = params;
+    }
+
     pub fn row_length(&self, row: usize) -> usize {
         self.row_lengths[row]
     }
@@ -295,10 +612,13 @@ impl GridActivations {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::step::StepGate;
 
     #[test]
     fn can_create_new_distribution() {
-        assert_eq!(create_new_distribution(5).len(), 5);
+        assert_eq!(create_new_distribution(5, 0).len(), 5);
+        assert_eq!(create_new_distribution(8, 1), vec![0, 7, 5, 3, 2, 1, 4, 6]);
+        assert_eq!(create_new_distribution(8, 2), vec![3, 5, 4, 7, 1, 0, 6, 2]);
     }
 
     #[test]
@@ -306,8 +626,10 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![false, false, false, false, false],
             thresh: vec![0, 1, 2, 4, 3],
+            params: vec![StepParams::default(); 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         seq.set_activations_for_new_density(0);
@@ -324,8 +646,10 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![false, true, false, false, true],
             thresh: vec![0, 1, 2, 4, 3],
+            params: vec![StepParams::default(); 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         assert_eq!(seq.num_active_steps(), 2);
@@ -338,8 +662,10 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![false, false, false, false, false],
             thresh: vec![0, 1, 2, 3, 4],
+            params: vec![StepParams::default(); 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         let density: usize = 1;
@@ -368,16 +694,40 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![false, true, false, false, true],
             thresh: vec![0, 1, 2, 3, 4],
+            params: vec![StepParams::default(); 5],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
-        // 2, 0, 4, 3, 1
-        seq.create_new_distribution_given_active_steps();
+        seq.create_new_distribution_given_active_steps(1);
 
         assert!(seq.thresh[0] >= 2);
         assert!(seq.thresh[1] < 2);
         assert!(seq.thresh[4] < 2);
+        assert_eq!(seq.thresh, vec![4, 0, 2, 3, 1]);
+        assert_eq!(seq.seed(), 1);
+    }
+
+    #[test]
+    fn test_same_seed_same_distribution() {
+        let mut a = GridActivations::new(3, 4, 42);
+        let mut b = GridActivations::new(3, 4, 42);
+        assert_eq!(a.thresh, b.thresh);
+        assert_eq!(a.thresh, vec![7, 11, 10, 8, 5, 4, 2, 3, 0, 6, 9, 1]);
+
+        // appended steps come from the seed too
+        a.set_row_length(1, 8);
+        b.set_row_length(1, 8);
+        assert_eq!(a.thresh, b.thresh);
+
+        a.set_num_active_steps(5);
+        b.set_num_active_steps(5);
+        a.create_new_distribution_given_active_steps(7);
+        b.create_new_distribution_given_active_steps(7);
+        assert_eq!(a.thresh, b.thresh);
+        assert_eq!(a.active, b.active);
+        assert_ne!(a.thresh, GridActivations::new(3, 4, 43).thresh);
     }
 
     #[test]
@@ -435,8 +785,10 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![true, true, true, true, true, true],
             thresh: vec![0, 1, 2, 3, 4, 5],
+            params: vec![StepParams::default(); 6],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         // insert a step at end of second row
@@ -462,8 +814,10 @@ mod tests {
         let mut seq = GridActivations {
             active: vec![],
             thresh: vec![],
+            params: vec![],
             row_lengths: vec![0, 0, 0],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         // insert a step at end of second row
@@ -473,13 +827,221 @@ mod tests {
         assert_eq!(seq.normalized_density, 0.0);
     }
 
+    #[test]
+    fn test_set_num_rows() {
+        let mut seq = GridActivations {
+            active: vec![true, false, true, false, true],
+            thresh: vec![0, 4, 1, 3, 2],
+            params: vec![StepParams::default(); 5],
+            row_lengths: vec![2, 3],
+            normalized_density: 0.0,
+            seed: 0,
+        };
+
+        // new rows are empty and their thresholds come after the existing ones
+        seq.set_num_rows(3, 2);
+        assert_eq!(seq.row_lengths, vec![2, 3, 2]);
+        assert_eq!(seq.active[0..5], [true, false, true, false, true]);
+        assert_eq!(seq.thresh[0..5], [0, 4, 1, 3, 2]);
+        let mut new_thresh = seq.thresh[5..].to_vec();
+        new_thresh.sort();
+        assert_eq!(new_thresh, vec![5, 6]);
+        assert_eq!(seq.get_row_activations().len(), 3);
+
+        // removing rows closes the gaps in the thresholds
+        seq.set_num_rows(1, 2);
+        assert_eq!(seq.row_lengths, vec![2]);
+        assert_eq!(seq.active, vec![true, false]);
+        assert_eq!(seq.thresh, vec![0, 1]);
+        assert_eq!(seq.normalized_density, 0.5);
+    }
+
+    #[test]
+    fn test_long_rows() {
+        let mut seq = GridActivations::new(2, 8, 0);
+        seq.set_num_active_steps(5);
+        let pattern = seq.get_row(0);
+
+        seq.set_row_length(0, MAX_ROW_LENGTH);
+        seq.set_row_length(1, MAX_ROW_LENGTH);
+        assert_eq!(seq.get_row(0)[0..8], pattern);
+
+        // the thresholds are still one of each
+        let mut thresh = seq.thresh.clone();
+        thresh.sort();
+        assert_eq!(thresh, Vec::from_iter(0..MAX_ROW_LENGTH * 2));
+
+        // every density adds exactly one more step to the ones before
+        let mut previous = vec![false; MAX_ROW_LENGTH * 2];
+        for num_active_steps in 0..=MAX_ROW_LENGTH * 2 {
+            seq.set_num_active_steps(num_active_steps);
+            assert_eq!(seq.num_active_steps(), num_active_steps);
+            assert!(previous
+                .iter()
+                .zip(seq.active.iter())
+                .all(|(p, a)| !p || *a));
+            previous = seq.active.clone();
+
+            // and going via the normalized density gives the same steps
+            let density = num_active_steps as f32 / seq.get_total_num_steps() as f32;
+            seq.set_normalized_density(density);
+            assert_eq!(seq.active, previous);
+        }
+    }
+
+    #[test]
+    fn test_bjorklund() {
+        let to_string = |pattern: Vec<bool>| -> String {
+            pattern
+                .iter()
+                .map(|&on| if on { 'x' } else { '.' })
+                .collect()
+        };
+        assert_eq!(to_string(bjorklund(3, 8)), "x..x..x.");
+        assert_eq!(to_string(bjorklund(5, 8)), "x.xx.xx.");
+        assert_eq!(to_string(bjorklund(4, 12)), "x..x..x..x..");
+        assert_eq!(to_string(bjorklund(0, 4)), "....");
+        assert_eq!(to_string(bjorklund(6, 4)), "xxxx");
+
+        let euclidean = Euclidean {
+            hits: 3,
+            steps: 8,
+            rotation: 2,
+        };
+        assert_eq!(to_string(euclidean.pattern()), "x.x..x..");
+    }
+
+    #[test]
+    fn test_euclidean_order() {
+        let pattern = bjorklund(3, 8);
+        assert_eq!(euclidean_order(&pattern), vec![0, 3, 6, 1, 4, 2, 5, 7]);
+        // with nothing in the pattern it starts from the first step
+        assert_eq!(euclidean_order(&[false; 4]), vec![0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn test_set_euclidean() {
+        let mut seq = GridActivations::new(2, 4, 0);
+        seq.set_num_active_steps(3);
+        let other_row = seq.get_row(1);
+
+        seq.set_euclidean(
+            0,
+            Euclidean {
+                hits: 3,
+                steps: 8,
+                rotation: 0,
+            },
+        );
+        assert_eq!(
+            seq.get_row(0),
+            vec![true, false, false, true, false, false, true, false]
+        );
+        assert_eq!(seq.get_row(1), other_row);
+
+        // the density goes up and down through the row in euclidean order
+        let row_thresh = seq.thresh[0..8].to_vec();
+        let mut order: Vec<usize> = (0..8).collect();
+        order.sort_by_key(|&i| row_thresh[i]);
+        assert_eq!(order, euclidean_order(&bjorklund(3, 8)));
+
+        let mut thresh = seq.thresh.clone();
+        thresh.sort();
+        assert_eq!(thresh, Vec::from_iter(0..12));
+    }
+
+    #[test]
+    fn test_set_row_num_active_steps() {
+        let mut seq = GridActivations::new(2, 8, 0);
+        seq.set_num_active_steps(8);
+        let other_row = seq.get_row(1);
+        let row_thresh = seq.thresh[0..8].to_vec();
+        let mut order: Vec<usize> = (0..8).collect();
+        order.sort_by_key(|&i| row_thresh[i]);
+
+        // the row thins out from its highest threshold down
+        seq.set_row_num_active_steps(0, 2);
+        assert_eq!(seq.row_num_active_steps(0), 2);
+        assert!(seq.get(0, order[0]) && seq.get(0, order[1]));
+        assert_eq!(seq.get_row(1), other_row);
+
+        // and fills up in the same order
+        seq.set_row_num_active_steps(0, 8);
+        seq.set_row_num_active_steps(0, 3);
+        let active: Vec<usize> = (0..8).filter(|&i| seq.get(0, i)).collect();
+        let mut expected = order[0..3].to_vec();
+        expected.sort();
+        assert_eq!(active, expected);
+        assert_eq!(seq.get_row(1), other_row);
+
+        // the thresholds still match the active steps, so the global density carries on working
+        let num_active_steps = seq.num_active_steps();
+        for i in 0..seq.active.len() {
+            assert_eq!(seq.active[i], seq.thresh[i] < num_active_steps);
+        }
+        let mut thresh = seq.thresh.clone();
+        thresh.sort();
+        assert_eq!(thresh, Vec::from_iter(0..16));
+        assert_eq!(seq.get_normalized_density(), num_active_steps as f32 / 16.0);
+    }
+
+    #[test]
+    fn test_pattern_text() {
+        let mut seq = GridActivations::new(2, 3, 5);
+        seq.set_num_active_steps(2);
+        seq.set(1, 2, true);
+        seq.set_row_length(0, 4);
+
+        let text = seq.to_pattern_text();
+        let loaded = GridActivations::parse_pattern(&text).unwrap();
+        assert_eq!(loaded.seed(), 5);
+        assert_eq!(loaded.row_lengths, seq.row_lengths);
+        assert_eq!(loaded.active, seq.active);
+        assert_eq!(loaded.thresh, seq.thresh);
+        assert_eq!(loaded.to_pattern_text(), text);
+
+        let loaded =
+            GridActivations::parse_pattern("seed 7\nx.x 0 2 1\n\n# a comment\n.. 3 4").unwrap();
+        assert_eq!(loaded.get_row(0), vec![true, false, true]);
+        assert_eq!(loaded.get_row(1), vec![false, false]);
+        assert_eq!(loaded.get_normalized_density(), 2.0 / 5.0);
+
+        assert!(GridActivations::parse_pattern("x.x 0 2 1").is_err());
+        assert!(GridActivations::parse_pattern("seed 7\nx.y 0 2 1").is_err());
+        // the thresholds have to agree with the active steps
+        assert!(GridActivations::parse_pattern("seed 7\nx.x 0 1 2").is_err());
+        assert!(GridActivations::parse_pattern("seed 7\nx.x 0 2").is_err());
+    }
+
+    #[test]
+    fn test_params_stay_with_their_step() {
+        let mut seq = GridActivations::new(2, 2, 0);
+        let tied = StepParams {
+            gate: StepGate::Tie,
+            velocity: Some(0.5),
+            ..Default::default()
+        };
+        seq.set_params(1, 0, tied);
+
+        // changing the length of the row before should not move the params
+        seq.set_row_length(0, 4);
+        assert_eq!(seq.get_params(1, 0), tied);
+        assert_eq!(seq.get_params(0, 3), StepParams::default());
+
+        seq.set_row_length(0, 1);
+        assert_eq!(seq.get_params(1, 0), tied);
+        assert_eq!(seq.get_row_steps(1)[0].params, tied);
+    }
+
     #[test]
     fn test_remove_steps() {
         let mut seq = GridActivations {
             active: vec![true, true, true, false, false, false],
             thresh: vec![0, 1, 2, 3, 4, 5],
+            params: vec![StepParams::default(); 6],
             row_lengths: vec![1, 2, 3],
             normalized_density: 0.0,
+            seed: 0,
         };
 
         // remove the second element of the second row, the third in the flat list
@@ -490,6 +1052,7 @@ mod tests {
 
         let expected_thresh: Vec<usize> = vec![0, 1, 2, 3, 4];
         assert_eq!(seq.thresh, expected_thresh);
+        assert_eq!(seq.params.len(), 5);
 
         let expected_row_lengths: Vec<usize> = vec![1, 1, 3];
         assert_eq!(seq.row_lengths, expected_row_lengths);
@@ -0,0 +1,164 @@
+// groove templates, per step timing offsets and velocity accents that make patterns less
+// mechanical
+
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Groove {
+    pub name: String,
+    timing: Vec<f32>,  // how late each step plays, as a fraction of a step
+    accents: Vec<f32>, // velocity scaling for each step
+}
+
+// notes can be delayed by up to this fraction of a step, any more and they'd run into the next
+pub const MAX_DELAY: f32 = 0.5;
+
+impl Default for Groove {
+    fn default() -> Self {
+        Self::straight()
+    }
+}
+
+impl Groove {
+    pub fn new(name: &str, timing: Vec<f32>, accents: Vec<f32>) -> Self {
+        Groove {
+            name: name.to_string(),
+            timing: timing.iter().map(|t| t.clamp(0.0, MAX_DELAY)).collect(),
+            accents: accents.iter().map(|a| a.clamp(0.0, 2.0)).collect(),
+        }
+    }
+
+    pub fn straight() -> Self {
+        Groove::new("Straight", vec![0.0], vec![1.0])
+    }
+
+    pub fn presets() -> Vec<Groove> {
+        vec![
+            Groove::straight(),
+            Groove::new(
+                "Shuffle 8",
+                vec![0.0, 0.17, 0.0, 0.17, 0.0, 0.17, 0.0, 0.17],
+                vec![1.0, 0.75, 0.9, 0.75, 1.0, 0.75, 0.9, 0.75],
+            ),
+            Groove::new(
+                "Accent 16",
+                vec![0.0; 16],
+                vec![
+                    1.0, 0.6, 0.8, 0.6, 0.9, 0.6, 0.8, 0.6, 1.0, 0.6, 0.8, 0.6, 0.9, 0.6, 0.8, 0.7,
+                ],
+            ),
+            Groove::new(
+                "Lazy 16",
+                vec![
+                    0.0, 0.08, 0.04, 0.12, 0.0, 0.1, 0.06, 0.14, 0.02, 0.08, 0.04, 0.12, 0.0, 0.1,
+                    0.06, 0.16,
+                ],
+                vec![
+                    1.0, 0.7, 0.85, 0.65, 0.95, 0.7, 0.8, 0.6, 1.0, 0.7, 0.85, 0.65, 0.95, 0.7,
+                    0.8, 0.75,
+                ],
+            ),
+        ]
+    }
+
+    // a groove file has one step per line, the timing offset then the accent e.g. "0.1 0.8"
+    // blank lines and lines starting with # are ignored
+    pub fn parse(name: &str, text: &str) -> Result<Groove, Box<dyn Error>> {
+        let mut timing = vec![];
+        let mut accents = vec![];
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut values = line.split_whitespace();
+            let offset: f32 = values.next().ok_or("Missing timing offset")?.parse()?;
+            let accent: f32 = match values.next() {
+                Some(value) => value.parse()?,
+                None => 1.0,
+            };
+            timing.push(offset);
+            accents.push(accent);
+        }
+        if timing.is_empty() {
+            return Err("Groove has no steps".into());
+        }
+        Ok(Groove::new(name, timing, accents))
+    }
+
+    pub fn load(path: &str) -> Result<Groove, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map_or(path.to_string(), |s| s.to_string_lossy().to_string());
+        Groove::parse(&name, &text)
+    }
+
+    pub fn len(&self) -> usize {
+        self.timing.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timing.is_empty()
+    }
+
+    // how late the step plays, as a fraction of a step
+    pub fn timing(&self, step: usize) -> f32 {
+        if self.timing.is_empty() {
+            return 0.0;
+        }
+        self.timing[step % self.timing.len()]
+    }
+
+    pub fn accent(&self, step: usize) -> f32 {
+        if self.accents.is_empty() {
+            return 1.0;
+        }
+        self.accents[step % self.accents.len()]
+    }
+}
+
+// swing is the percentage of a pair of steps taken by the first step, 50% is straight
+// returns how late the step plays as a fraction of a step
+pub fn swing_delay(swing: f32, step: usize) -> f32 {
+    if step % 2 == 0 {
+        return 0.0;
+    }
+    ((swing / 100.0 - 0.5) * 2.0).clamp(0.0, MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swing_delay() {
+        assert_eq!(swing_delay(50.0, 1), 0.0);
+        assert_eq!(swing_delay(75.0, 0), 0.0);
+        assert_eq!(swing_delay(75.0, 1), 0.5);
+        assert_eq!(swing_delay(62.5, 3), 0.25);
+    }
+
+    #[test]
+    fn test_parse_groove() {
+        let text = "# a test groove\n0.0 1.0\n0.25 0.5\n\n0.1\n";
+        let groove = Groove::parse("test", text).unwrap();
+        assert_eq!(groove.len(), 3);
+        assert_eq!(groove.timing(1), 0.25);
+        assert_eq!(groove.accent(1), 0.5);
+        // accent defaults to 1
+        assert_eq!(groove.accent(2), 1.0);
+        // wraps round
+        assert_eq!(groove.timing(4), 0.25);
+
+        assert!(Groove::parse("empty", "# nothing\n").is_err());
+        assert!(Groove::parse("bad", "zero one\n").is_err());
+    }
+
+    #[test]
+    fn test_groove_is_clamped() {
+        let groove = Groove::new("clamped", vec![2.0], vec![5.0]);
+        assert_eq!(groove.timing(0), MAX_DELAY);
+        assert_eq!(groove.accent(0), 2.0);
+    }
+}
@@ -1,24 +1,85 @@
 // run the egui update function
 
-use crate::grid_activations::GridActivations;
+use crate::grid_activations::{Euclidean, GridActivations};
+use crate::groove::Groove;
 use crate::messages::*;
-use crate::rho_config::NUM_ROWS;
+use crate::midi_helpers::{get_port_names, MidiError};
+use crate::midi_ports::RowRoute;
+use crate::note_assigner::{
+    Note, NoteOrdering, NoteWrapping, OctaveDirection, RowAssign, RowPitch,
+};
+use crate::rho::RowRate;
+use crate::rho_config::{DEFAULT_NUM_ROWS, MAX_ROWS, MAX_ROW_LENGTH, MAX_TEMPO, MIN_TEMPO};
+use crate::scale::{Scale, ScaleKind, NOTE_NAMES};
+use crate::scheduler::JitterStats;
+use crate::step::{Ratchet, StepGate, StepParams, TrigCondition};
 use crate::step_switch::*;
+use crate::velocity::{RowVelocity, VelocityCurve};
 use eframe::egui;
 use midir::{MidiInput, MidiOutput};
-use std::time::Duration;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// the number of steps in a new row
+const DEFAULT_ROW_LENGTH: usize = 4;
+// longer rows are shown a page at a time
+const STEPS_PER_PAGE: usize = 16;
+// steps don't get narrower than this, the row goes off the side of the window instead
+const MIN_STEP_WIDTH: f32 = 16.0;
+
+// how much faster or slower the nudge buttons play while they are held
+const NUDGE_AMOUNT: f32 = 0.04;
 
 struct UiState {
     // these vars are persistent across frames
     selected_in_port: usize,
     selected_out_port: usize,
     midi_in_channel: u8,
+    midi_in_omni: bool,
     midi_out_channel: u8,
+    midi_clock_out_ports: HashSet<usize>,
+    out_port_names: Vec<String>, // from the last frame, for the row routing
+    row_routes: Vec<RowRoute>,
     note_strings_for_rows: Vec<String>,
-    hold_checkbox_enabled: bool,
-    playing_steps_for_rows: [Option<usize>; NUM_ROWS],
+    note_ordering: NoteOrdering,
+    row_assign: RowAssign,
+    note_wrapping: NoteWrapping,
+    invert_rows: bool,
+    octave_fill: bool,
+    octave_direction: OctaveDirection,
+    max_octaves: usize,
+    playing_steps_for_rows: Vec<Option<usize>>,
+    row_rates: Vec<RowRate>,
+    row_gate_lengths: Vec<Option<f32>>,
+    gate_length: f32,
+    velocity_curve: VelocityCurve,
+    fixed_velocity_enabled: bool,
+    fixed_velocity: usize,
+    row_velocities: Vec<RowVelocity>,
+    row_pitches: Vec<RowPitch>,
+    row_euclideans: Vec<Option<Euclidean>>, // the rows that were last set by the euclidean generator
+    scale: Scale,
     playing: bool,
     tempo: f32,
+    nudge: f32,
+    fill: bool,
+    page: usize,
+    follow_page: bool, // each row shows the page with its playing step
+    seed_text: String, // the seed of the distribution, as it's being typed
+    pattern_path: String,
+    pattern_error: Option<String>,
+    tap_cc_enabled: bool,
+    tap_cc: u8,
+    external_sync: bool,
+    external_tempo: Option<f32>,
+    clock_jitter: Option<JitterStats>,
+    midi_error: Option<MidiError>,
+    send_panic_ccs: bool,
+    swing: f32,
+    grooves: Vec<Groove>,
+    selected_groove: usize,
+    groove_path: String,
+    groove_error: Option<String>,
 }
 
 impl UiState {
@@ -27,14 +88,65 @@ impl UiState {
             selected_in_port: 0,
             selected_out_port: 0,
             midi_in_channel: 0,
+            midi_in_omni: true,
             midi_out_channel: 0,
-            note_strings_for_rows: vec!["".to_string(); NUM_ROWS],
-            hold_checkbox_enabled: false,
-            playing_steps_for_rows: [None; NUM_ROWS],
+            midi_clock_out_ports: HashSet::new(),
+            out_port_names: vec![],
+            row_routes: vec![RowRoute::default(); DEFAULT_NUM_ROWS],
+            note_strings_for_rows: vec!["".to_string(); DEFAULT_NUM_ROWS],
+            note_ordering: NoteOrdering::default(),
+            row_assign: RowAssign::default(),
+            note_wrapping: NoteWrapping::default(),
+            invert_rows: false,
+            octave_fill: false,
+            octave_direction: OctaveDirection::default(),
+            max_octaves: 3,
+            playing_steps_for_rows: vec![None; DEFAULT_NUM_ROWS],
+            row_rates: vec![RowRate::Normal; DEFAULT_NUM_ROWS],
+            row_gate_lengths: vec![None; DEFAULT_NUM_ROWS],
+            gate_length: 0.5,
+            velocity_curve: VelocityCurve::default(),
+            fixed_velocity_enabled: false,
+            fixed_velocity: 100,
+            row_velocities: vec![RowVelocity::default(); DEFAULT_NUM_ROWS],
+            row_pitches: vec![RowPitch::default(); DEFAULT_NUM_ROWS],
+            row_euclideans: vec![None; DEFAULT_NUM_ROWS],
+            scale: Scale::default(),
             playing: false,
             tempo: 120.0,
+            nudge: 0.0,
+            fill: false,
+            page: 0,
+            follow_page: false,
+            seed_text: String::new(),
+            pattern_path: String::new(),
+            pattern_error: None,
+            tap_cc_enabled: false,
+            tap_cc: 64,
+            external_sync: false,
+            external_tempo: None,
+            clock_jitter: None,
+            midi_error: None,
+            send_panic_ccs: false,
+            swing: 50.0,
+            grooves: Groove::presets(),
+            selected_groove: 0,
+            groove_path: String::new(),
+            groove_error: None,
         }
     }
+
+    // the settings of removed rows are dropped, rho drops its copy too
+    fn set_num_rows(&mut self, num_rows: usize) {
+        self.row_routes.resize(num_rows, RowRoute::default());
+        self.note_strings_for_rows.resize(num_rows, String::new());
+        self.playing_steps_for_rows.resize(num_rows, None);
+        self.row_rates.resize(num_rows, RowRate::Normal);
+        self.row_gate_lengths.resize(num_rows, None);
+        self.row_velocities.resize(num_rows, RowVelocity::default());
+        self.row_pitches.resize(num_rows, RowPitch::default());
+        self.row_euclideans.resize(num_rows, None);
+    }
 }
 
 // gui takes ownership of the grid
@@ -43,7 +155,7 @@ pub fn run_gui(
     tx: std::sync::mpsc::Sender<MessageGuiToRho>,
 ) {
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([600.0, 600.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([900.0, 600.0]),
         default_theme: eframe::Theme::Dark,
         follow_system_theme: false,
         ..Default::default()
@@ -52,7 +164,8 @@ pub fn run_gui(
     let mut ui_state = UiState::new();
 
     // grid could go in UiState too
-    let mut grid = GridActivations::new(4, 4);
+    let mut grid = GridActivations::new(DEFAULT_NUM_ROWS, DEFAULT_ROW_LENGTH, new_seed());
+    ui_state.seed_text = grid.seed().to_string();
 
     // TODO send all the intial gui state to Rho
     let _ = tx.send(MessageGuiToRho::SetTempo {
@@ -71,58 +184,110 @@ pub fn run_gui(
 
         egui::CentralPanel::default().show(ctx, |ui| {
             // first recieve messages from the clock thread
-            match rx.try_recv() {
-                Ok(MessageToGui::Tick { playing_steps }) => {
-                    ui_state.playing_steps_for_rows = playing_steps;
-                    ctx.request_repaint();
-                }
-                Ok(MessageToGui::NotesForRows { notes }) => {
-                    // assign notes to the note_strings_for_rows
-                    for i in 0..NUM_ROWS {
-                        let mut note_str = String::new();
-                        for note in notes[i].iter() {
-                            note_str.push_str(&format!("{} ", note));
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    MessageToGui::Tick { playing_steps } => {
+                        ui_state.playing_steps_for_rows = playing_steps;
+                        ctx.request_repaint();
+                    }
+                    MessageToGui::NotesForRows { notes } => {
+                        // assign notes to the note_strings_for_rows
+                        for (i, row_notes) in notes.iter().enumerate() {
+                            let mut note_str = String::new();
+                            for note in row_notes.iter() {
+                                note_str.push_str(&format!("{} ", note));
+                            }
+                            if let Some(row_str) = ui_state.note_strings_for_rows.get_mut(i) {
+                                *row_str = note_str.clone();
+                            }
+                            ctx.request_repaint();
                         }
-                        ui_state.note_strings_for_rows[i] = note_str.clone();
+                    }
+                    MessageToGui::ExternalTempo { bpm } => {
+                        ui_state.external_tempo = Some(bpm);
+                        ctx.request_repaint();
+                    }
+                    MessageToGui::Tempo { bpm } => {
+                        ui_state.tempo = bpm;
+                        ctx.request_repaint();
+                    }
+                    MessageToGui::ClockJitter { jitter } => {
+                        ui_state.clock_jitter = Some(jitter);
+                    }
+                    MessageToGui::MidiError { error } => {
+                        ui_state.midi_error = Some(error);
                         ctx.request_repaint();
                     }
                 }
-                _ => (),
             }
 
-            let mut density: usize = (grid.get_normalized_density() * 127.0) as usize;
+            let mut num_active_steps = grid.num_active_steps();
 
-            for row in (0..NUM_ROWS).rev() {
-                let playing_step = ui_state.playing_steps_for_rows[row];
+            for row in (0..grid.num_rows()).rev() {
+                // the playing steps can be from before the rows changed
+                let playing_step = ui_state.playing_steps_for_rows.get(row).copied().flatten();
                 do_send_row_activations = do_send_row_activations
-                    || draw_row(ui, &mut grid, &mut ui_state, row, playing_step);
+                    || draw_row(ui, &mut grid, &mut ui_state, &tx, row, playing_step);
             }
 
             ui.horizontal(|ui| {
+                // each step of the slider adds or removes one step, however long the rows are
                 if ui
-                    .add(egui::Slider::new(&mut density, 0..=127).text("density"))
+                    .add(
+                        egui::Slider::new(&mut num_active_steps, 0..=grid.get_total_num_steps())
+                            .text("density"),
+                    )
                     .changed()
                 {
-                    let norm_density = density as f32 / 127.0;
-                    grid.set_normalized_density(norm_density);
+                    grid.set_num_active_steps(num_active_steps);
                     do_send_row_activations = true;
                 }
 
                 if ui.button("New Dist").clicked() {
-                    grid.create_new_distribution_given_active_steps();
+                    grid.create_new_distribution_given_active_steps(new_seed());
+                    ui_state.seed_text = grid.seed().to_string();
                     do_send_row_activations = true;
                 }
 
+                // type in a seed to redistribute the active steps with it. Pressing enter uses
+                // the seed again even if it hasn't changed, e.g. to undo edits to the thresholds
+                ui.label("Seed");
+                let response =
+                    ui.add(egui::TextEdit::singleline(&mut ui_state.seed_text).desired_width(80.0));
+                if response.lost_focus() {
+                    let entered = ui.input(|i| i.key_pressed(egui::Key::Enter))
+                        || ui_state.seed_text != grid.seed().to_string();
+                    if let Ok(seed) = ui_state.seed_text.trim().parse() {
+                        if entered {
+                            grid.create_new_distribution_given_active_steps(seed);
+                            do_send_row_activations = true;
+                        }
+                    }
+                    ui_state.seed_text = grid.seed().to_string();
+                }
+
+                let mut num_rows = grid.num_rows();
                 if ui
-                    .checkbox(&mut ui_state.hold_checkbox_enabled, "Hold")
+                    .add(egui::Slider::new(&mut num_rows, 1..=MAX_ROWS).text("Rows"))
                     .changed()
                 {
-                    let _ = tx.send(MessageGuiToRho::HoldNotesEnabled {
-                        enabled: ui_state.hold_checkbox_enabled,
-                    });
+                    grid.set_num_rows(num_rows, DEFAULT_ROW_LENGTH);
+                    ui_state.set_num_rows(num_rows);
+                    let _ = tx.send(MessageGuiToRho::SetNumRows { num_rows });
+                    do_send_row_activations = true;
                 }
+
+                draw_page_controls(ui, &grid, &mut ui_state);
             });
 
+            do_send_row_activations |= draw_pattern_file(ui, &mut grid, &mut ui_state, &tx);
+
+            draw_note_modes(ui, &mut ui_state, &tx);
+            draw_scale_controls(ui, &mut ui_state, &tx);
+
+            draw_timing_controls(ui, &mut ui_state, &tx);
+            draw_velocity_controls(ui, &mut ui_state, &tx);
+
             if do_send_row_activations {
                 let _ = tx.send(MessageGuiToRho::RowActivations {
                     row_activations: grid.get_row_activations(),
@@ -139,6 +304,7 @@ fn draw_row(
     ui: &mut egui::Ui,
     grid: &mut GridActivations,
     ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
     row: usize,
     playing_step: Option<usize>,
 ) -> bool {
@@ -150,7 +316,8 @@ fn draw_row(
         let spacing = ui.spacing().item_spacing;
 
         let fixed_left_width = 100.0;
-        let fixed_right_width = 200.0;
+        // the row length slider and the settings button
+        let fixed_right_width = 240.0;
 
         // a text display of the note for this row
         ui.add_sized(
@@ -158,42 +325,715 @@ fn draw_row(
             egui::Label::new(&ui_state.note_strings_for_rows[row]),
         );
 
-        // draw the row of steps
+        // draw the page of steps
         let mut row_length = grid.row_length(row);
+        let steps = page_steps(
+            row_length,
+            ui_state.page,
+            ui_state.follow_page,
+            playing_step,
+        );
 
         let steps_width = ui.available_size().x
             - fixed_left_width
             - fixed_right_width
-            - spacing.x * (row_length - 1) as f32;
+            - spacing.x * (steps.len() - 1) as f32;
 
-        let step_width = steps_width / row_length as f32;
-        for step in 0..row_length {
+        let step_width = (steps_width / steps.len() as f32).max(MIN_STEP_WIDTH);
+        for step in steps {
             let mut active = grid.get(row, step);
+            let mut params = grid.get_params(row, step);
             let is_playing = playing_step == Some(step);
 
             // set the size on this step switch
-            if ui
-                .add_sized([step_width, 50.0], step_switch(&mut active, is_playing))
-                .changed()
-            {
+            let response = ui.add_sized(
+                [step_width, 50.0],
+                step_switch(&mut active, &mut params.velocity, is_playing),
+            );
+            if response.changed() {
                 grid.set(row, step, active);
+                grid.set_params(row, step, params);
                 do_send_row_activations = true;
             }
+
+            // right click for the step settings
+            response.context_menu(|ui| {
+                let mut params = grid.get_params(row, step);
+                if draw_step_settings(ui, &mut params) {
+                    grid.set_params(row, step, params);
+                    do_send_row_activations = true;
+                }
+            });
         }
 
         // todo replace with +- buttons
         if ui
-            .add(egui::Slider::new(&mut row_length, 2..=8).text("Row Length"))
+            .add(egui::Slider::new(&mut row_length, 2..=MAX_ROW_LENGTH).text("Row Length"))
             .changed()
         {
             grid.set_row_length(row, row_length);
             do_send_row_activations = true;
         }
+
+        ui.menu_button("⚙", |ui| {
+            ui.horizontal(|ui| {
+                let response = egui::ComboBox::from_id_source(("row_rate", row))
+                    .width(60.0)
+                    .selected_text(ui_state.row_rates[row].name())
+                    .show_ui(ui, |ui| {
+                        let mut changed = false;
+                        for rate in RowRate::ALL {
+                            changed |= ui
+                                .selectable_value(&mut ui_state.row_rates[row], rate, rate.name())
+                                .changed();
+                        }
+                        changed
+                    });
+
+                if response.inner == Some(true) {
+                    let _ = tx.send(MessageGuiToRho::SetRowRate {
+                        row,
+                        rate: ui_state.row_rates[row],
+                    });
+                }
+                ui.label("Rate");
+            });
+            ui.horizontal(|ui| draw_row_pitch(ui, ui_state, tx, row));
+            ui.separator();
+            draw_row_settings(ui, ui_state, tx, row);
+            ui.separator();
+
+            // the density of just this row, the main density slider still changes all of them
+            let mut row_num_active_steps = grid.row_num_active_steps(row);
+            if ui
+                .add(
+                    egui::Slider::new(&mut row_num_active_steps, 0..=grid.row_length(row))
+                        .text("Density"),
+                )
+                .changed()
+            {
+                grid.set_row_num_active_steps(row, row_num_active_steps);
+                do_send_row_activations = true;
+            }
+            do_send_row_activations |= draw_euclidean_settings(ui, grid, ui_state, row);
+        });
     });
 
     do_send_row_activations
 }
 
+// the steps of a row to show. Rows shorter than the page show their last page
+fn page_steps(
+    row_length: usize,
+    page: usize,
+    follow_page: bool,
+    playing_step: Option<usize>,
+) -> std::ops::Range<usize> {
+    let last_page = row_length.saturating_sub(1) / STEPS_PER_PAGE;
+    let page = match playing_step {
+        Some(step) if follow_page => step / STEPS_PER_PAGE,
+        _ => page,
+    }
+    .min(last_page);
+    let start = page * STEPS_PER_PAGE;
+    start..row_length.min(start + STEPS_PER_PAGE)
+}
+
+// save the pattern with its seed, or load one to replace the grid
+fn draw_pattern_file(
+    ui: &mut egui::Ui,
+    grid: &mut GridActivations,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut ui_state.pattern_path).desired_width(120.0));
+        if ui.button("Save Pattern").clicked() {
+            ui_state.pattern_error = grid
+                .save(&ui_state.pattern_path)
+                .err()
+                .map(|e| format!("Couldn't save {}: {}", ui_state.pattern_path, e));
+        }
+        if ui.button("Load Pattern").clicked() {
+            match GridActivations::load(&ui_state.pattern_path) {
+                Ok(loaded) => {
+                    *grid = loaded;
+                    let num_rows = grid.num_rows();
+                    ui_state.set_num_rows(num_rows);
+                    ui_state.row_euclideans.fill(None);
+                    ui_state.seed_text = grid.seed().to_string();
+                    ui_state.pattern_error = None;
+                    let _ = tx.send(MessageGuiToRho::SetNumRows { num_rows });
+                    changed = true;
+                }
+                Err(e) => {
+                    ui_state.pattern_error =
+                        Some(format!("Couldn't load {}: {}", ui_state.pattern_path, e));
+                }
+            }
+        }
+
+        if let Some(error) = &ui_state.pattern_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error);
+            if ui.small_button("x").clicked() {
+                ui_state.pattern_error = None;
+            }
+        }
+    });
+    changed
+}
+
+// seeds are kept short so they're easy to share
+fn new_seed() -> u64 {
+    rand::random::<u32>() as u64
+}
+
+// choose the page shown when rows are longer than a page
+fn draw_page_controls(ui: &mut egui::Ui, grid: &GridActivations, ui_state: &mut UiState) {
+    let longest_row = (0..grid.num_rows())
+        .map(|row| grid.row_length(row))
+        .max()
+        .unwrap_or(0);
+    let num_pages = longest_row.max(1).div_ceil(STEPS_PER_PAGE);
+    ui_state.page = ui_state.page.min(num_pages - 1);
+
+    ui.add_enabled_ui(!ui_state.follow_page, |ui| {
+        if ui.button("<").clicked() {
+            ui_state.page = ui_state.page.saturating_sub(1);
+        }
+        ui.label(format!("Page {}/{}", ui_state.page + 1, num_pages));
+        if ui.button(">").clicked() {
+            ui_state.page = (ui_state.page + 1).min(num_pages - 1);
+        }
+    });
+    ui.checkbox(&mut ui_state.follow_page, "Follow");
+}
+
+// transpose the row, or set it to always play the same note
+fn draw_row_pitch(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+    row: usize,
+) {
+    let pitch = &mut ui_state.row_pitches[row];
+    let mut fixed = pitch.fixed_note.is_some();
+    let mut fixed_note = pitch.fixed_note.unwrap_or(36);
+
+    let mut changed = false;
+    ui.add_enabled_ui(!fixed, |ui| {
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut pitch.transpose)
+                    .clamp_range(-12..=12)
+                    .prefix("St "),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::DragValue::new(&mut pitch.octave)
+                    .clamp_range(-4..=4)
+                    .prefix("Oct "),
+            )
+            .changed();
+    });
+    changed |= ui.checkbox(&mut fixed, "Fixed").changed();
+    changed |= ui
+        .add_enabled(
+            fixed,
+            egui::DragValue::new(&mut fixed_note)
+                .clamp_range(0..=127)
+                .custom_formatter(|n, _| {
+                    Note {
+                        note_number: n as usize,
+                        velocity: 0,
+                    }
+                    .to_string()
+                }),
+        )
+        .changed();
+
+    if changed {
+        pitch.fixed_note = fixed.then_some(fixed_note);
+        let _ = tx.send(MessageGuiToRho::SetRowPitch { row, pitch: *pitch });
+    }
+}
+
+// fill the row with a euclidean rhythm. The row is only rewritten when the settings change, so
+// the steps can still be edited by hand afterwards
+fn draw_euclidean_settings(
+    ui: &mut egui::Ui,
+    grid: &mut GridActivations,
+    ui_state: &mut UiState,
+    row: usize,
+) -> bool {
+    let mut enabled = ui_state.row_euclideans[row].is_some();
+    let mut euclidean = ui_state.row_euclideans[row].unwrap_or(Euclidean {
+        steps: grid.row_length(row),
+        ..Default::default()
+    });
+
+    let mut changed = ui.checkbox(&mut enabled, "Euclidean").changed();
+    ui.add_enabled_ui(enabled, |ui| {
+        changed |= ui
+            .add(egui::Slider::new(&mut euclidean.steps, 2..=MAX_ROW_LENGTH).text("Steps"))
+            .changed();
+        euclidean.hits = euclidean.hits.min(euclidean.steps);
+        euclidean.rotation = euclidean.rotation.min(euclidean.steps - 1);
+        changed |= ui
+            .add(egui::Slider::new(&mut euclidean.hits, 0..=euclidean.steps).text("Hits"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut euclidean.rotation, 0..=euclidean.steps - 1)
+                    .text("Rotation"),
+            )
+            .changed();
+    });
+
+    ui_state.row_euclideans[row] = enabled.then_some(euclidean);
+    if changed && enabled {
+        grid.set_euclidean(row, euclidean);
+        return true;
+    }
+    false
+}
+
+// the less used settings for a row, in a menu
+fn draw_row_settings(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+    row: usize,
+) {
+    let mut own_gate = ui_state.row_gate_lengths[row].is_some();
+    let mut gate_length = ui_state.row_gate_lengths[row].unwrap_or(ui_state.gate_length);
+    let mut gate_changed = ui.checkbox(&mut own_gate, "Row Gate").changed();
+    gate_changed |= ui
+        .add_enabled(
+            own_gate,
+            egui::Slider::new(&mut gate_length, 0.05..=2.0).text("Gate"),
+        )
+        .changed();
+    if gate_changed {
+        ui_state.row_gate_lengths[row] = own_gate.then_some(gate_length);
+        let _ = tx.send(MessageGuiToRho::SetRowGateLength {
+            row,
+            length: ui_state.row_gate_lengths[row],
+        });
+    }
+
+    let velocity = &mut ui_state.row_velocities[row];
+    let mut velocity_changed = ui
+        .add(egui::Slider::new(&mut velocity.scale, 0.0..=2.0).text("Velocity Scale"))
+        .changed();
+    velocity_changed |= ui
+        .add(egui::Slider::new(&mut velocity.offset, -64..=64).text("Velocity Offset"))
+        .changed();
+    if velocity_changed {
+        let _ = tx.send(MessageGuiToRho::SetRowVelocity {
+            row,
+            velocity: *velocity,
+        });
+    }
+
+    // by default rows go to the main out port and channel
+    ui.separator();
+    let route = &mut ui_state.row_routes[row];
+    let mut own_channel = route.channel.is_some();
+    let mut channel = route.channel.unwrap_or(ui_state.midi_out_channel);
+    let mut route_changed = ui.checkbox(&mut own_channel, "Row Channel").changed();
+    route_changed |= ui
+        .add_enabled(
+            own_channel,
+            egui::DragValue::new(&mut channel).clamp_range(0..=15),
+        )
+        .changed();
+    route.channel = own_channel.then_some(channel);
+
+    let out_port_names = &ui_state.out_port_names;
+    let response = egui::ComboBox::from_label("Row Port")
+        .selected_text(
+            route
+                .port
+                .map_or("Main Port", |port| port_name(out_port_names, port)),
+        )
+        .show_ui(ui, |ui| {
+            let mut changed = ui
+                .selectable_value(&mut route.port, None, "Main Port")
+                .changed();
+            for (i, name) in out_port_names.iter().enumerate() {
+                changed |= ui
+                    .selectable_value(&mut route.port, Some(i), name)
+                    .changed();
+            }
+            changed
+        });
+    route_changed |= response.inner == Some(true);
+
+    if route_changed {
+        ui_state.midi_error = None;
+        let _ = tx.send(MessageGuiToRho::SetRowRoute { row, route: *route });
+    }
+}
+
+// the settings for a single step, returns true if they changed
+fn draw_step_settings(ui: &mut egui::Ui, params: &mut StepParams) -> bool {
+    let mut changed = false;
+
+    ui.label("Gate");
+    let length = match params.gate {
+        StepGate::Length(length) => length,
+        _ => 0.5,
+    };
+    changed |= ui
+        .radio_value(&mut params.gate, StepGate::Row, "Row")
+        .changed();
+    changed |= ui
+        .radio_value(&mut params.gate, StepGate::Length(length), "Length")
+        .changed();
+    changed |= ui
+        .radio_value(&mut params.gate, StepGate::Tie, "Tie")
+        .changed();
+    if let StepGate::Length(length) = &mut params.gate {
+        changed |= ui.add(egui::Slider::new(length, 0.05..=2.0)).changed();
+    }
+
+    // can also be set by dragging the step up and down
+    ui.separator();
+    let mut own_velocity = params.velocity.is_some();
+    let mut velocity = params.velocity.unwrap_or(1.0);
+    let mut velocity_changed = ui.checkbox(&mut own_velocity, "Velocity").changed();
+    velocity_changed |= ui
+        .add_enabled(own_velocity, egui::Slider::new(&mut velocity, 0.0..=1.0))
+        .changed();
+    if velocity_changed {
+        params.velocity = own_velocity.then_some(velocity);
+        changed = true;
+    }
+
+    ui.separator();
+    let mut own_probability = params.probability.is_some();
+    let mut probability = params.probability.unwrap_or(1.0) * 100.0;
+    let mut probability_changed = ui.checkbox(&mut own_probability, "Probability").changed();
+    probability_changed |= ui
+        .add_enabled(
+            own_probability,
+            egui::Slider::new(&mut probability, 0.0..=100.0).suffix("%"),
+        )
+        .changed();
+    if probability_changed {
+        params.probability = own_probability.then_some(probability / 100.0);
+        changed = true;
+    }
+
+    let response = egui::ComboBox::from_label("Condition")
+        .selected_text(params.condition.name())
+        .show_ui(ui, |ui| {
+            let mut changed = false;
+            for condition in TrigCondition::ALL {
+                changed |= ui
+                    .selectable_value(&mut params.condition, condition, condition.name())
+                    .changed();
+            }
+            changed
+        });
+    changed |= response.inner == Some(true);
+
+    ui.separator();
+    let mut ratcheted = params.ratchet.is_some();
+    let mut ratchet = params.ratchet.unwrap_or_default();
+    let mut ratchet_changed = ui.checkbox(&mut ratcheted, "Ratchet").changed();
+    ui.add_enabled_ui(ratcheted, |ui| {
+        ratchet_changed |= ui
+            .add(egui::Slider::new(&mut ratchet.hits, 2..=Ratchet::MAX_HITS).text("Hits"))
+            .changed();
+        ratchet_changed |= ui
+            .add(egui::Slider::new(&mut ratchet.velocity_ramp, -1.0..=1.0).text("Velocity Ramp"))
+            .changed();
+        ratchet_changed |= ui
+            .add(egui::Slider::new(&mut ratchet.pitch_ramp, -12..=12).text("Pitch Ramp"))
+            .changed();
+    });
+    if ratchet_changed {
+        params.ratchet = ratcheted.then_some(ratchet);
+        changed = true;
+    }
+
+    changed
+}
+
+// how the held notes are spread over the rows
+fn draw_note_modes(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+) {
+    ui.horizontal(|ui| {
+        let response = egui::ComboBox::from_label("Order")
+            .selected_text(ui_state.note_ordering.name())
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for ordering in NoteOrdering::ALL {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.note_ordering, ordering, ordering.name())
+                        .changed();
+                }
+                changed
+            });
+        if response.inner == Some(true) {
+            let _ = tx.send(MessageGuiToRho::SetNoteOrdering {
+                ordering: ui_state.note_ordering,
+            });
+        }
+
+        let response = egui::ComboBox::from_label("Rows")
+            .selected_text(ui_state.row_assign.name())
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for mode in RowAssign::ALL {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.row_assign, mode, mode.name())
+                        .changed();
+                }
+                changed
+            });
+        if response.inner == Some(true) {
+            let _ = tx.send(MessageGuiToRho::SetRowAssign {
+                mode: ui_state.row_assign,
+            });
+        }
+
+        let response = egui::ComboBox::from_label("Wrap")
+            .selected_text(ui_state.note_wrapping.name())
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for wrapping in NoteWrapping::ALL {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.note_wrapping, wrapping, wrapping.name())
+                        .changed();
+                }
+                changed
+            });
+        if response.inner == Some(true) {
+            let _ = tx.send(MessageGuiToRho::SetNoteWrapping {
+                wrapping: ui_state.note_wrapping,
+            });
+        }
+
+        // puts the lowest note on the top row
+        if ui
+            .checkbox(&mut ui_state.invert_rows, "Invert Rows")
+            .changed()
+        {
+            let _ = tx.send(MessageGuiToRho::SetInvertRows {
+                enabled: ui_state.invert_rows,
+            });
+        }
+    });
+
+    ui.horizontal(|ui| {
+        let mut changed = ui
+            .checkbox(&mut ui_state.octave_fill, "Octave Fill")
+            .changed();
+        ui.add_enabled_ui(ui_state.octave_fill, |ui| {
+            let response = egui::ComboBox::from_id_source("octave_direction")
+                .selected_text(ui_state.octave_direction.name())
+                .show_ui(ui, |ui| {
+                    let mut changed = false;
+                    for direction in OctaveDirection::ALL {
+                        changed |= ui
+                            .selectable_value(
+                                &mut ui_state.octave_direction,
+                                direction,
+                                direction.name(),
+                            )
+                            .changed();
+                    }
+                    changed
+                });
+            changed |= response.inner == Some(true);
+            changed |= ui
+                .add(egui::Slider::new(&mut ui_state.max_octaves, 1..=4).text("Octaves"))
+                .changed();
+        });
+        if changed {
+            let _ = tx.send(MessageGuiToRho::SetOctaveFill {
+                enabled: ui_state.octave_fill,
+                direction: ui_state.octave_direction,
+                max_octaves: ui_state.max_octaves,
+            });
+        }
+    });
+}
+
+// the key and scale that the notes are moved into, a custom scale picks its notes with the toggles
+fn draw_scale_controls(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+) {
+    ui.horizontal(|ui| {
+        let scale = &mut ui_state.scale;
+        let mut changed = false;
+
+        let response = egui::ComboBox::from_label("Key")
+            .width(50.0)
+            .selected_text(NOTE_NAMES[scale.root])
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for (root, name) in NOTE_NAMES.iter().enumerate() {
+                    changed |= ui.selectable_value(&mut scale.root, root, *name).changed();
+                }
+                changed
+            });
+        changed |= response.inner == Some(true);
+
+        let response = egui::ComboBox::from_label("Scale")
+            .selected_text(scale.kind.name())
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for kind in ScaleKind::ALL {
+                    changed |= ui
+                        .selectable_value(&mut scale.kind, kind, kind.name())
+                        .changed();
+                }
+                changed
+            });
+        changed |= response.inner == Some(true);
+
+        if scale.kind == ScaleKind::Custom {
+            for degree in 0..12 {
+                let name = NOTE_NAMES[(scale.root + degree) % 12];
+                let mut in_scale = scale.custom_mask & (1 << degree) != 0;
+                if ui.toggle_value(&mut in_scale, name).changed() {
+                    scale.custom_mask ^= 1 << degree;
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            let _ = tx.send(MessageGuiToRho::SetScale { scale: *scale });
+        }
+    });
+}
+
+// the velocity follows the held notes unless it's fixed, then goes through the curve
+fn draw_velocity_controls(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+) {
+    ui.horizontal(|ui| {
+        let response = egui::ComboBox::from_label("Velocity Curve")
+            .selected_text(ui_state.velocity_curve.name())
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for curve in VelocityCurve::ALL {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.velocity_curve, curve, curve.name())
+                        .changed();
+                }
+                changed
+            });
+        if response.inner == Some(true) {
+            let _ = tx.send(MessageGuiToRho::SetVelocityCurve {
+                curve: ui_state.velocity_curve,
+            });
+        }
+
+        let fixed_changed = ui
+            .checkbox(&mut ui_state.fixed_velocity_enabled, "Fixed Velocity")
+            .changed()
+            || ui
+                .add_enabled(
+                    ui_state.fixed_velocity_enabled,
+                    egui::DragValue::new(&mut ui_state.fixed_velocity).clamp_range(1..=127),
+                )
+                .changed();
+        if fixed_changed {
+            let _ = tx.send(MessageGuiToRho::SetFixedVelocity {
+                velocity: ui_state
+                    .fixed_velocity_enabled
+                    .then_some(ui_state.fixed_velocity),
+            });
+        }
+    });
+}
+
+// swing amount, groove template selection and loading a groove from a file, and the gate length
+fn draw_timing_controls(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    tx: &std::sync::mpsc::Sender<MessageGuiToRho>,
+) {
+    ui.horizontal(|ui| {
+        if ui
+            .add(egui::Slider::new(&mut ui_state.gate_length, 0.05..=2.0).text("Gate"))
+            .changed()
+        {
+            let _ = tx.send(MessageGuiToRho::SetGateLength {
+                length: ui_state.gate_length,
+            });
+        }
+
+        if ui
+            .add(egui::Slider::new(&mut ui_state.swing, 50.0..=75.0).text("Swing %"))
+            .changed()
+        {
+            let _ = tx.send(MessageGuiToRho::SetSwing {
+                swing: ui_state.swing,
+            });
+        }
+
+        let response = egui::ComboBox::from_label("Groove")
+            .selected_text(&ui_state.grooves[ui_state.selected_groove].name)
+            .show_ui(ui, |ui| {
+                let mut changed = false;
+                for (i, groove) in ui_state.grooves.iter().enumerate() {
+                    changed |= ui
+                        .selectable_value(&mut ui_state.selected_groove, i, &groove.name)
+                        .changed();
+                }
+                changed
+            });
+
+        let mut groove_changed = response.inner == Some(true);
+
+        ui.add(egui::TextEdit::singleline(&mut ui_state.groove_path).desired_width(120.0));
+        if ui.button("Load Groove").clicked() {
+            match Groove::load(&ui_state.groove_path) {
+                Ok(groove) => {
+                    ui_state.grooves.push(groove);
+                    ui_state.selected_groove = ui_state.grooves.len() - 1;
+                    ui_state.groove_error = None;
+                    groove_changed = true;
+                }
+                Err(e) => {
+                    ui_state.groove_error =
+                        Some(format!("Couldn't load {}: {}", ui_state.groove_path, e));
+                }
+            }
+        }
+
+        // like the midi errors, this stays up until it's dismissed or a groove loads
+        if let Some(error) = &ui_state.groove_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error);
+            if ui.small_button("x").clicked() {
+                ui_state.groove_error = None;
+            }
+        }
+
+        if groove_changed {
+            let _ = tx.send(MessageGuiToRho::SetGroove {
+                groove: ui_state.grooves[ui_state.selected_groove].clone(),
+            });
+        }
+    });
+}
+
 fn top_panel(
     ctx: &egui::Context,
     ui_state: &mut UiState,
@@ -201,70 +1041,81 @@ fn top_panel(
 ) {
     // set up midi list here TODO this happens every frame! Might be slow
     // could instead use a popup window to set midi ports and if they come and go then we don't care
-    let midi_in = MidiInput::new("midir input").unwrap();
-    let in_ports = midi_in.ports();
-    let mut in_port_names: Vec<String> = in_ports
-        .iter()
-        .map(|port| midi_in.port_name(port).unwrap())
-        .collect();
-
-    if in_port_names.len() == 0 {
+    let mut in_port_names = MidiInput::new("midir input")
+        .map(|midi_in| get_port_names(&midi_in))
+        .unwrap_or_default();
+
+    if in_port_names.is_empty() {
         in_port_names.push("No Midi In Ports".to_string());
     }
 
-    let midi_out = MidiOutput::new("midir output").unwrap();
-    let out_ports = midi_out.ports();
-    // let in_port_name = midi_in.port_name(&in_port)?;
-    let mut out_port_names: Vec<String> = out_ports
-        .iter()
-        .map(|port| midi_out.port_name(port).unwrap())
-        .collect();
+    let mut out_port_names = MidiOutput::new("midir output")
+        .map(|midi_out| get_port_names(&midi_out))
+        .unwrap_or_default();
 
-    if out_port_names.len() == 0 {
+    if out_port_names.is_empty() {
         out_port_names.push("No Midi Out Ports".to_string());
     }
+    ui_state.out_port_names = out_port_names.clone();
 
     egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
         ui.heading("Rho Sequencer");
 
         ui.horizontal(|ui| {
             let response = egui::ComboBox::from_label("Midi In Port")
-                .selected_text(format!("{:?}", in_port_names[ui_state.selected_in_port]))
+                .selected_text(format!(
+                    "{:?}",
+                    port_name(&in_port_names, ui_state.selected_in_port)
+                ))
                 .show_ui(ui, |ui| {
-                    let mut i = 0;
-                    for port in in_port_names.iter() {
-                        ui.selectable_value(&mut ui_state.selected_in_port, i, port);
-                        i += 1;
+                    let mut changed = false;
+                    for (i, port) in in_port_names.iter().enumerate() {
+                        changed |= ui
+                            .selectable_value(&mut ui_state.selected_in_port, i, port)
+                            .changed();
                     }
+                    changed
                 });
 
             // if the midi port selection was changed, send a message to the clock thread
-            if response.response.changed() {
+            if response.inner == Some(true) {
+                ui_state.midi_error = None;
                 let _ = tx.send(MessageGuiToRho::SetMidiInPort {
                     port: ui_state.selected_in_port,
                 });
             }
 
-            if ui
-                .add(egui::DragValue::new(&mut ui_state.midi_in_channel).clamp_range(0..=15))
-                .changed()
-            {
+            // omni listens to notes on every channel
+            let channel_in_changed = ui.checkbox(&mut ui_state.midi_in_omni, "Omni").changed()
+                || ui
+                    .add_enabled(
+                        !ui_state.midi_in_omni,
+                        egui::DragValue::new(&mut ui_state.midi_in_channel).clamp_range(0..=15),
+                    )
+                    .changed();
+            if channel_in_changed {
                 let _ = tx.send(MessageGuiToRho::SetMidiChannelIn {
-                    channel: ui_state.midi_in_channel,
+                    channel: (!ui_state.midi_in_omni).then_some(ui_state.midi_in_channel),
                 });
             }
 
             let response = egui::ComboBox::from_label("Midi Out Port")
-                .selected_text(format!("{:?}", out_port_names[ui_state.selected_out_port]))
+                .selected_text(format!(
+                    "{:?}",
+                    port_name(&out_port_names, ui_state.selected_out_port)
+                ))
                 .show_ui(ui, |ui| {
-                    let mut i = 0;
-                    for port in out_port_names.iter() {
-                        ui.selectable_value(&mut ui_state.selected_out_port, i, port);
-                        i += 1;
+                    let mut changed = false;
+                    for (i, port) in out_port_names.iter().enumerate() {
+                        changed |= ui
+                            .selectable_value(&mut ui_state.selected_out_port, i, port)
+                            .changed();
                     }
+                    changed
                 });
 
-            if response.response.changed() {
+            if response.inner == Some(true) {
+                ui_state.midi_error = None;
                 let _ = tx.send(MessageGuiToRho::SetMidiOutPort {
                     port: ui_state.selected_out_port,
                 });
@@ -278,26 +1129,151 @@ fn top_panel(
                     channel: ui_state.midi_out_channel,
                 });
             }
+
+            // midi clock out is enabled per output port
+            let port = ui_state.selected_out_port;
+            let mut send_clock = ui_state.midi_clock_out_ports.contains(&port);
+            if ui.checkbox(&mut send_clock, "Send Clock").changed() {
+                if send_clock {
+                    ui_state.midi_clock_out_ports.insert(port);
+                } else {
+                    ui_state.midi_clock_out_ports.remove(&port);
+                }
+                let _ = tx.send(MessageGuiToRho::SetMidiClockOut {
+                    port,
+                    enabled: send_clock,
+                });
+            }
         });
 
+        // midi errors stay up until they're dismissed or another port is chosen
+        if let Some(error) = &ui_state.midi_error {
+            let text = error.to_string();
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::LIGHT_RED, text);
+                if ui.small_button("x").clicked() {
+                    ui_state.midi_error = None;
+                }
+            });
+        }
+
         ui.add_space(10.0);
 
         // add transport controls
         ui.horizontal(|ui| {
             if ui.checkbox(&mut ui_state.playing, "Play").clicked() {
-                let _ = tx.send(MessageGuiToRho::SetPlaying { playing: true });
+                let _ = tx.send(MessageGuiToRho::SetPlaying {
+                    playing: ui_state.playing,
+                });
+            }
+            if ui.button("Rewind").clicked() {
+                let _ = tx.send(MessageGuiToRho::Rewind);
+            }
+
+            // fill lasts as long as the button is held
+            let fill = ui.button("Fill").is_pointer_button_down_on();
+            if fill != ui_state.fill {
+                ui_state.fill = fill;
+                let _ = tx.send(MessageGuiToRho::SetFill { enabled: fill });
+            }
+
+            if ui.button("Panic").clicked() {
+                let _ = tx.send(MessageGuiToRho::Panic);
+            }
+            if ui
+                .checkbox(&mut ui_state.send_panic_ccs, "Panic CCs")
+                .on_hover_text("Send all notes off and all sound off whenever notes are stopped")
+                .changed()
+            {
+                let _ = tx.send(MessageGuiToRho::SetPanicCcs {
+                    enabled: ui_state.send_panic_ccs,
+                });
             }
 
+            // the tempo slider does nothing when following an external clock
             if ui
-                .add(egui::Slider::new(&mut ui_state.tempo, 40.0..=1000.0).text("Tempo"))
+                .add_enabled(
+                    !ui_state.external_sync,
+                    egui::Slider::new(&mut ui_state.tempo, MIN_TEMPO..=MAX_TEMPO).text("Tempo"),
+                )
                 .changed()
             {
                 let _ = tx.send(MessageGuiToRho::SetTempo {
                     tempo: ui_state.tempo,
                 });
             }
+
+            ui.add_enabled_ui(!ui_state.external_sync, |ui| {
+                if ui.button("Tap").clicked() {
+                    let _ = tx.send(MessageGuiToRho::TapTempo {
+                        time: Instant::now(),
+                    });
+                }
+
+                // the nudge lasts as long as the button is held
+                let slower = ui.button("-").on_hover_text("Nudge slower");
+                let faster = ui.button("+").on_hover_text("Nudge faster");
+                let mut nudge = 0.0;
+                if slower.is_pointer_button_down_on() {
+                    nudge = -NUDGE_AMOUNT;
+                } else if faster.is_pointer_button_down_on() {
+                    nudge = NUDGE_AMOUNT;
+                }
+                if nudge != ui_state.nudge {
+                    ui_state.nudge = nudge;
+                    let _ = tx.send(MessageGuiToRho::SetNudge { nudge });
+                }
+            });
+
+            // a midi cc can tap the tempo too
+            let tap_cc_changed = ui
+                .checkbox(&mut ui_state.tap_cc_enabled, "Tap CC")
+                .changed()
+                || ui
+                    .add_enabled(
+                        ui_state.tap_cc_enabled,
+                        egui::DragValue::new(&mut ui_state.tap_cc).clamp_range(0..=127),
+                    )
+                    .changed();
+            if tap_cc_changed {
+                let _ = tx.send(MessageGuiToRho::SetTapCc {
+                    cc: ui_state.tap_cc_enabled.then_some(ui_state.tap_cc),
+                });
+            }
+
+            if ui
+                .checkbox(&mut ui_state.external_sync, "Ext Sync")
+                .changed()
+            {
+                ui_state.external_tempo = None;
+                let _ = tx.send(MessageGuiToRho::SetExternalSync {
+                    enabled: ui_state.external_sync,
+                });
+            }
+
+            if ui_state.external_sync {
+                let tempo_text = match ui_state.external_tempo {
+                    Some(bpm) => format!("{:.1} BPM", bpm),
+                    None => "-- BPM".to_string(),
+                };
+                ui.label(tempo_text);
+            } else if let Some(jitter) = ui_state.clock_jitter {
+                // how late the pulses are, so the timing can be checked
+                ui.label(format!(
+                    "Jitter {}µs avg, {}µs max",
+                    jitter.mean().as_micros(),
+                    jitter.max().as_micros()
+                ));
+            }
         });
 
         ui.add_space(10.0);
     });
 }
+
+// the selected port might have been unplugged since it was chosen
+fn port_name(port_names: &[String], port: usize) -> &str {
+    port_names
+        .get(port)
+        .map_or("Missing Port", |name| name.as_str())
+}
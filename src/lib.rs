@@ -5,12 +5,20 @@ extern crate rand;
 pub mod clock;
 pub mod clock_runner;
 pub mod grid_activations;
+pub mod groove;
 pub mod gui_runner;
 pub mod looping_state;
 pub mod messages;
 pub mod midi_helpers;
+pub mod midi_ports;
 pub mod note_assigner;
 pub mod phasor;
 pub mod rho;
 pub mod rho_config;
+pub mod scale;
+pub mod scheduler;
+pub mod step;
 pub mod step_switch;
+pub mod tap_tempo;
+pub mod tempo_estimator;
+pub mod velocity;
@@ -10,10 +10,7 @@ where
     T: Copy,
 {
     pub fn new(data: Vec<T>) -> Self {
-        Self {
-            data: data,
-            counter: 0,
-        }
+        Self { data, counter: 0 }
     }
 
     pub fn clear(&mut self) {
@@ -24,6 +21,15 @@ where
     pub fn reset(&mut self) {
         self.counter = 0;
     }
+
+    // set which step will be returned next, wrapping round the length
+    pub fn set_position(&mut self, position: usize) {
+        if self.data.is_empty() {
+            self.counter = 0;
+        } else {
+            self.counter = position % self.data.len();
+        }
+    }
     pub fn append(&mut self, value: T) {
         self.data.push(value);
     }
@@ -35,7 +41,7 @@ where
         }
         // adjust counter to be within bounds
         if self.counter >= new_length {
-            self.counter = self.counter % new_length;
+            self.counter %= new_length;
         }
         self.data.resize(new_length, value);
     }
@@ -54,9 +60,13 @@ where
         }
     }
 
+    pub fn get_step(&self, step: usize) -> Option<T> {
+        self.data.get(step).copied()
+    }
+
     pub fn get_current_step(&self) -> usize {
         // counter is post incremented so we need to subtract 1
-        if self.data.len() == 0 {
+        if self.data.is_empty() {
             return 0;
         }
         ((self.counter + self.data.len()) - 1) % self.data.len()
@@ -125,4 +135,15 @@ mod tests {
         assert_eq!(s.next(), Some(10));
         assert_eq!(s.next(), Some(10));
     }
+
+    #[test]
+    fn test_set_position() {
+        let mut s = LoopingSequence::new(vec![10, 20, 30]);
+
+        s.set_position(2);
+        assert_eq!(s.next(), Some(30));
+        s.set_position(4);
+        assert_eq!(s.next(), Some(20));
+        assert_eq!(s.get_current_step(), 1);
+    }
 }
@@ -1,51 +1,177 @@
 // inter thread messages
 
-use crate::note_assigner::Note;
-use crate::rho_config::NUM_ROWS;
+use crate::groove::Groove;
+use crate::midi_helpers::MidiError;
+use crate::midi_ports::RowRoute;
+use crate::note_assigner::{
+    Note, NoteOrdering, NoteWrapping, OctaveDirection, RowAssign, RowPitch,
+};
+use crate::rho::RowRate;
+use crate::scale::Scale;
+use crate::scheduler::JitterStats;
+use crate::step::Step;
+use crate::velocity::{RowVelocity, VelocityCurve};
+use std::time::Instant;
 
 pub const NOTE_ON_MSG: u8 = 0x90;
 pub const NOTE_OFF_MSG: u8 = 0x80;
+pub const CONTROL_CHANGE_MSG: u8 = 0xB0;
+
+// channel mode control changes, for stopping stuck notes
+pub const ALL_SOUND_OFF_CC: u8 = 120;
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+
+// system real time messages, used for midi clock
+pub const SONG_POSITION_MSG: u8 = 0xF2;
+pub const TIMING_CLOCK_MSG: u8 = 0xF8;
+pub const START_MSG: u8 = 0xFA;
+pub const CONTINUE_MSG: u8 = 0xFB;
+pub const STOP_MSG: u8 = 0xFC;
 
 // when notes are recieved, we send them to the rho sequencer via a channel
+#[derive(Debug, PartialEq)]
 pub enum MidiInMessage {
-    NoteOn(u8, u8),
-    NoteOff(u8),
+    // channel, note and velocity
+    NoteOn(u8, u8, u8),
+    // channel and note
+    NoteOff(u8, u8),
+    // channel, controller number and value
+    ControlChange(u8, u8, u8),
+    // midi clock, with the timestamp in microseconds
+    TimingClock(u64),
+    Start,
+    Continue,
+    Stop,
+    // position in midi beats (sixteenth notes)
+    SongPosition(u16),
 }
 
 // messages from the clock to the gui, to display the state of the sequencer
 pub enum MessageToGui {
-    NotesForRows {
-        notes: [Vec<Note>; NUM_ROWS],
-    },
-    Tick {
-        playing_steps: [Option<usize>; NUM_ROWS],
-    },
+    NotesForRows { notes: Vec<Vec<Note>> },
+    Tick { playing_steps: Vec<Option<usize>> },
+    ExternalTempo { bpm: f32 },
+    // the tempo was changed by tapping
+    Tempo { bpm: f32 },
+    MidiError { error: MidiError },
+    // how late the internal clock's pulses have been recently
+    ClockJitter { jitter: JitterStats },
 }
 
 // messages from the gui to the rho sequencer (clock thread). send when the row activations change
 pub enum MessageGuiToRho {
     RowActivations {
-        row_activations: [Vec<bool>; NUM_ROWS],
+        row_activations: Vec<Vec<Step>>,
+    },
+    // the row settings are sent separately, so send this first
+    SetNumRows {
+        num_rows: usize,
     },
-    HoldNotesEnabled {
+    SetNoteOrdering {
+        ordering: NoteOrdering,
+    },
+    SetRowAssign {
+        mode: RowAssign,
+    },
+    SetNoteWrapping {
+        wrapping: NoteWrapping,
+    },
+    // assign the notes from the top row down
+    SetInvertRows {
         enabled: bool,
     },
+    // fill rows that have no notes with octaves of the held notes
+    SetOctaveFill {
+        enabled: bool,
+        direction: OctaveDirection,
+        max_octaves: usize,
+    },
     SetMidiInPort {
         port: usize,
     },
     SetMidiOutPort {
         port: usize,
     },
+    SetMidiClockOut {
+        port: usize,
+        enabled: bool,
+    },
+    // None listens to all channels
     SetMidiChannelIn {
-        channel: u8,
+        channel: Option<u8>,
     },
     SetMidiChannelOut {
         channel: u8,
     },
+    // send a row to its own port and channel
+    SetRowRoute {
+        row: usize,
+        route: RowRoute,
+    },
     SetPlaying {
         playing: bool,
     },
+    // go back to the beginning, so the next play is a start rather than a continue
+    Rewind,
     SetTempo {
         tempo: f32,
     },
+    SetVelocityCurve {
+        curve: VelocityCurve,
+    },
+    // None follows the velocity of the held notes
+    SetFixedVelocity {
+        velocity: Option<usize>,
+    },
+    SetRowVelocity {
+        row: usize,
+        velocity: RowVelocity,
+    },
+    TapTempo {
+        time: Instant,
+    },
+    // the midi cc that taps the tempo, if any
+    SetTapCc {
+        cc: Option<u8>,
+    },
+    SetNudge {
+        nudge: f32,
+    },
+    SetExternalSync {
+        enabled: bool,
+    },
+    SetRowPitch {
+        row: usize,
+        pitch: RowPitch,
+    },
+    SetScale {
+        scale: Scale,
+    },
+    // steps with the fill condition play while this is on
+    SetFill {
+        enabled: bool,
+    },
+    // stop every note on every channel
+    Panic,
+    // also send the all notes off and all sound off ccs whenever notes are stopped
+    SetPanicCcs {
+        enabled: bool,
+    },
+    SetRowRate {
+        row: usize,
+        rate: RowRate,
+    },
+    SetSwing {
+        swing: f32,
+    },
+    SetGroove {
+        groove: Groove,
+    },
+    SetGateLength {
+        length: f32,
+    },
+    SetRowGateLength {
+        row: usize,
+        length: Option<f32>,
+    },
 }
@@ -3,15 +3,67 @@
 use crate::messages::*;
 use midir::{Ignore, MidiIO, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 use std::error::Error;
-use std::io::{stdin, stdout, Write};
+use std::fmt;
 use std::sync::mpsc::Sender;
 
+// everything that can go wrong talking to midi devices. the midir errors are kept as text so
+// that these can be cloned and sent to the gui
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiError {
+    Init(String),       // couldn't start up the midi system
+    InvalidPort(usize), // there's no port with this index, it might have been unplugged
+    PortInfo(String),   // couldn't get the name of a port
+    Connect(String),    // couldn't connect to the port
+    NotConnected,       // tried to send before an output port was chosen
+    Send(String),       // sending failed, usually because the device has gone
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiError::Init(e) => write!(f, "Couldn't start midi: {}", e),
+            MidiError::InvalidPort(port) => write!(f, "No midi port {}", port),
+            MidiError::PortInfo(e) => write!(f, "Couldn't get midi port info: {}", e),
+            MidiError::Connect(e) => write!(f, "Couldn't connect to midi port: {}", e),
+            MidiError::NotConnected => write!(f, "No midi out port selected"),
+            MidiError::Send(e) => write!(f, "Couldn't send midi: {}", e),
+        }
+    }
+}
+
+impl Error for MidiError {}
+
+impl From<midir::InitError> for MidiError {
+    fn from(e: midir::InitError) -> Self {
+        MidiError::Init(e.to_string())
+    }
+}
+
+impl From<midir::PortInfoError> for MidiError {
+    fn from(e: midir::PortInfoError) -> Self {
+        MidiError::PortInfo(e.to_string())
+    }
+}
+
+impl<T> From<midir::ConnectError<T>> for MidiError {
+    fn from(e: midir::ConnectError<T>) -> Self {
+        MidiError::Connect(e.to_string())
+    }
+}
+
+impl From<midir::SendError> for MidiError {
+    fn from(e: midir::SendError) -> Self {
+        MidiError::Send(e.to_string())
+    }
+}
+
 pub fn set_up_midi_in_connection(
     tx: Sender<MidiInMessage>,
-) -> Result<MidiInputConnection<Sender<MidiInMessage>>, Box<dyn Error>> {
+    port_index: usize,
+) -> Result<MidiInputConnection<Sender<MidiInMessage>>, MidiError> {
     let mut midi_in = MidiInput::new("midir input")?;
     midi_in.ignore(Ignore::None);
-    let in_port = select_port(&midi_in, "input", 0)?;
+    let in_port = select_port(&midi_in, "input", port_index)?;
 
     let conn_in = midi_in.connect(
         &in_port,
@@ -25,33 +77,49 @@ pub fn set_up_midi_in_connection(
 }
 
 // when a midi in message is recieved, we call this function
-pub fn on_midi_in(tx: &mut std::sync::mpsc::Sender<MidiInMessage>, _stamp: u64, message: &[u8]) {
+pub fn on_midi_in(tx: &mut std::sync::mpsc::Sender<MidiInMessage>, stamp: u64, message: &[u8]) {
     //println!("{}: {:?} (len = {})", stamp, message, message.len());
 
-    const MSG_NOTE: u8 = 144;
-    const MSG_NOTE_2: u8 = 145;
-    const MSG_NOTE_OFF: u8 = 129;
-
+    if message.is_empty() {
+        return;
+    }
     let status = message[0];
+
+    // midi clock messages are a single byte, apart from song position
+    let clock_message = match status {
+        TIMING_CLOCK_MSG => Some(MidiInMessage::TimingClock(stamp)),
+        START_MSG => Some(MidiInMessage::Start),
+        CONTINUE_MSG => Some(MidiInMessage::Continue),
+        STOP_MSG => Some(MidiInMessage::Stop),
+        SONG_POSITION_MSG if message.len() >= 3 => Some(MidiInMessage::SongPosition(
+            ((message[2] as u16) << 7) | message[1] as u16,
+        )),
+        _ => None,
+    };
+    if let Some(clock_message) = clock_message {
+        let _ = tx.send(clock_message);
+        return;
+    }
+
+    if message.len() < 3 {
+        return;
+    }
+    let channel = status & 0x0F;
     let note = message[1];
     let velocity = message[2];
 
-    if status == MSG_NOTE || status == MSG_NOTE_2 {
-        if velocity > 0 {
-            print!("sending note on {:?}\n", note);
-            tx.send(MidiInMessage::NoteOn(note, velocity)).unwrap(); // TODO this can panic!
-        } else {
-            tx.send(MidiInMessage::NoteOff(note)).unwrap();
-        }
-    } else if status == MSG_NOTE_OFF {
-        tx.send(MidiInMessage::NoteOff(note)).unwrap();
-    }
+    let _ = match status & 0xF0 {
+        NOTE_ON_MSG if velocity > 0 => tx.send(MidiInMessage::NoteOn(channel, note, velocity)),
+        // a note on with zero velocity is a note off
+        NOTE_ON_MSG | NOTE_OFF_MSG => tx.send(MidiInMessage::NoteOff(channel, note)),
+        CONTROL_CHANGE_MSG => tx.send(MidiInMessage::ControlChange(channel, note, velocity)),
+        _ => Ok(()),
+    };
 }
 
-pub fn get_midi_out_connection(port_index: usize) -> Result<MidiOutputConnection, Box<dyn Error>> {
+pub fn get_midi_out_connection(port_index: usize) -> Result<MidiOutputConnection, MidiError> {
     let midi_out = MidiOutput::new("midir output")?;
 
-    println!();
     let out_port = select_port(&midi_out, "output", port_index)?;
 
     // let in_port_name = midi_in.port_name(&in_port)?;
@@ -71,10 +139,53 @@ pub fn get_midi_out_connection(port_index: usize) -> Result<MidiOutputConnection
 
 pub fn select_port<T: MidiIO>(
     midi_io: &T,
-    descr: &str,
+    _descr: &str,
     port_index: usize,
-) -> Result<T::Port, Box<dyn Error>> {
+) -> Result<T::Port, MidiError> {
     let midi_ports = midi_io.ports();
-    let port = midi_ports.get(port_index).ok_or("Invalid port number")?;
+    let port = midi_ports
+        .get(port_index)
+        .ok_or(MidiError::InvalidPort(port_index))?;
     Ok(port.clone())
 }
+
+// the names of all the ports, for showing in the gui
+pub fn get_port_names<T: MidiIO>(midi_io: &T) -> Vec<String> {
+    midi_io
+        .ports()
+        .iter()
+        .map(|port| {
+            midi_io
+                .port_name(port)
+                .unwrap_or_else(|_| "Unknown".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_on_midi_in_channels() {
+        let (mut tx, rx) = mpsc::channel();
+        on_midi_in(&mut tx, 0, &[NOTE_ON_MSG + 3, 60, 100]);
+        on_midi_in(&mut tx, 0, &[NOTE_ON_MSG + 3, 60, 0]);
+        on_midi_in(&mut tx, 0, &[NOTE_OFF_MSG + 15, 62, 64]);
+        on_midi_in(&mut tx, 0, &[CONTROL_CHANGE_MSG + 1, 64, 127]);
+        on_midi_in(&mut tx, 0, &[TIMING_CLOCK_MSG]);
+
+        let messages: Vec<MidiInMessage> = rx.try_iter().collect();
+        assert_eq!(
+            messages,
+            vec![
+                MidiInMessage::NoteOn(3, 60, 100),
+                MidiInMessage::NoteOff(3, 60),
+                MidiInMessage::NoteOff(15, 62),
+                MidiInMessage::ControlChange(1, 64, 127),
+                MidiInMessage::TimingClock(0),
+            ]
+        );
+    }
+}
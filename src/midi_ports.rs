@@ -0,0 +1,350 @@
+// the midi in and out connections used by the clock thread
+
+use crate::messages::*;
+use crate::midi_helpers::*;
+use crate::note_assigner::Note;
+use crate::rho_config::MAX_ROWS;
+use midir::{MidiInputConnection, MidiOutputConnection};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+
+// the midi in connection, which can be swapped for another port while running
+pub struct MidiIn {
+    conn: Option<MidiInputConnection<Sender<MidiInMessage>>>,
+    // each connection gets a copy of this to send what it recieves to the clock thread
+    tx: Sender<MidiInMessage>,
+    // None listens to all channels
+    pub channel: Option<u8>,
+}
+
+impl MidiIn {
+    pub fn new(tx: Sender<MidiInMessage>) -> Self {
+        MidiIn {
+            conn: None,
+            tx,
+            channel: None,
+        }
+    }
+
+    pub fn connect(&mut self, port: usize) -> Result<(), MidiError> {
+        // close the old connection first, the same port can't always be opened twice
+        if let Some(conn) = self.conn.take() {
+            conn.close();
+        }
+        self.conn = Some(set_up_midi_in_connection(self.tx.clone(), port)?);
+        Ok(())
+    }
+
+    pub fn listens_to(&self, channel: u8) -> bool {
+        self.channel.map_or(true, |c| c == channel)
+    }
+}
+
+// where a row's notes are sent, None uses the main output port or channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RowRoute {
+    pub port: Option<usize>,
+    pub channel: Option<u8>,
+}
+
+// everything needed to send midi to the output ports
+pub struct MidiOut {
+    // a connection for every port in use, the main one and any that rows are routed to
+    conns: HashMap<usize, MidiOutputConnection>,
+    port: Option<usize>,
+    pub channel: u8,
+    row_routes: Vec<RowRoute>, // rows after the end use the main port and channel
+    // the output ports that we send midi clock to
+    pub clock_ports: HashSet<usize>,
+    // notes that have been sent a note on but no note off yet, as (port, channel, note)
+    sounding_notes: HashSet<(Option<usize>, u8, u8)>,
+    // send the all notes off and all sound off ccs too when stopping everything
+    pub send_panic_ccs: bool,
+    // sending carries on after an error, but each error is only reported once
+    last_error: Option<MidiError>,
+    new_errors: Vec<MidiError>,
+}
+
+impl Default for MidiOut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiOut {
+    pub fn new() -> Self {
+        MidiOut {
+            conns: HashMap::new(),
+            port: None,
+            channel: 0,
+            row_routes: vec![],
+            clock_ports: HashSet::new(),
+            sounding_notes: HashSet::new(),
+            send_panic_ccs: false,
+            last_error: None,
+            new_errors: vec![],
+        }
+    }
+
+    // if the connection fails there's no output until another port is chosen
+    pub fn connect(&mut self, port: usize) -> Result<(), MidiError> {
+        // don't leave anything hanging on the old port
+        self.stop_all_notes();
+        self.port = None;
+        self.last_error = None;
+        self.close_unused_ports();
+        self.open(port)?;
+        self.port = Some(port);
+        Ok(())
+    }
+
+    // send a row somewhere other than the main port and channel. If the port can't be opened
+    // the row's notes are lost until it is routed again
+    pub fn set_row_route(&mut self, row: usize, route: RowRoute) -> Result<(), MidiError> {
+        if row >= MAX_ROWS {
+            return Ok(());
+        }
+        self.stop_all_notes();
+        if row >= self.row_routes.len() {
+            self.row_routes.resize(row + 1, RowRoute::default());
+        }
+        self.row_routes[row] = route;
+        self.last_error = None;
+        self.close_unused_ports();
+        match route.port {
+            Some(port) => self.open(port),
+            None => Ok(()),
+        }
+    }
+
+    // removed rows lose their routes, so rows added later start on the main port and channel
+    pub fn set_num_rows(&mut self, num_rows: usize) {
+        self.stop_all_notes();
+        self.row_routes
+            .resize(num_rows.min(MAX_ROWS), RowRoute::default());
+        self.close_unused_ports();
+    }
+
+    fn open(&mut self, port: usize) -> Result<(), MidiError> {
+        if let Entry::Vacant(entry) = self.conns.entry(port) {
+            entry.insert(get_midi_out_connection(port)?);
+        }
+        Ok(())
+    }
+
+    fn close_unused_ports(&mut self) {
+        let main_port = self.port;
+        let routes = &self.row_routes;
+        self.conns.retain(|&port, _| {
+            main_port == Some(port) || routes.iter().any(|route| route.port == Some(port))
+        });
+    }
+
+    // the port and channel a row's notes go to
+    fn destination(&self, row: usize) -> (Option<usize>, u8) {
+        let route = self.row_routes.get(row).copied().unwrap_or_default();
+        (
+            route.port.or(self.port),
+            route.channel.unwrap_or(self.channel),
+        )
+    }
+
+    // the errors since this was last called
+    pub fn take_errors(&mut self) -> Vec<MidiError> {
+        std::mem::take(&mut self.new_errors)
+    }
+
+    // send a midi clock message to each of the open ports that clock out is enabled for
+    pub fn send_clock_message(&mut self, message: u8) {
+        let mut ports: Vec<usize> = self
+            .conns
+            .keys()
+            .copied()
+            .filter(|port| self.clock_ports.contains(port))
+            .collect();
+        ports.sort();
+        for port in ports {
+            self.send(Some(port), &[message]);
+        }
+    }
+
+    pub fn send_note_on(&mut self, row: usize, note: &Note) {
+        let (port, channel) = self.destination(row);
+        let note_number = note.note_number as u8;
+        self.sounding_notes.insert((port, channel, note_number));
+        self.send(
+            port,
+            &[NOTE_ON_MSG + channel, note_number, note.velocity as u8],
+        );
+    }
+
+    pub fn send_note_off(&mut self, row: usize, note: &Note) {
+        let (port, channel) = self.destination(row);
+        let note_number = note.note_number as u8;
+        self.sounding_notes.remove(&(port, channel, note_number));
+        self.send(port, &[NOTE_OFF_MSG + channel, note_number, 0x64]);
+    }
+
+    // the notes still sounding, in order
+    pub fn sounding_notes(&self) -> Vec<(Option<usize>, u8, u8)> {
+        let mut notes: Vec<(Option<usize>, u8, u8)> = self.sounding_notes.iter().copied().collect();
+        notes.sort();
+        notes
+    }
+
+    // send a note off for every note that is still sounding, on the port and channel it was
+    // sent on
+    pub fn stop_all_notes(&mut self) {
+        let notes = self.sounding_notes();
+        self.sounding_notes.clear();
+        for &(port, channel, note_number) in notes.iter() {
+            self.send(port, &[NOTE_OFF_MSG + channel, note_number, 0x64]);
+        }
+
+        if self.send_panic_ccs {
+            // the channels that had notes or that any row plays on
+            let mut destinations: Vec<(Option<usize>, u8)> = notes
+                .iter()
+                .map(|&(port, channel, _)| (port, channel))
+                .chain(std::iter::once((self.port, self.channel)))
+                .chain((0..self.row_routes.len()).map(|row| self.destination(row)))
+                .collect();
+            destinations.sort();
+            destinations.dedup();
+            for (port, channel) in destinations {
+                if port.map_or(false, |port| self.conns.contains_key(&port)) {
+                    self.send_panic_ccs(port, &[channel]);
+                }
+            }
+        }
+    }
+
+    // for when a synth has got stuck, stop everything on every channel of every port
+    pub fn panic(&mut self) {
+        self.stop_all_notes();
+        let mut ports: Vec<usize> = self.conns.keys().copied().collect();
+        ports.sort();
+        let channels: Vec<u8> = (0..16).collect();
+        for port in ports {
+            self.send_panic_ccs(Some(port), &channels);
+        }
+    }
+
+    fn send_panic_ccs(&mut self, port: Option<usize>, channels: &[u8]) {
+        for &channel in channels {
+            self.send(port, &[CONTROL_CHANGE_MSG + channel, ALL_NOTES_OFF_CC, 0]);
+            self.send(port, &[CONTROL_CHANGE_MSG + channel, ALL_SOUND_OFF_CC, 0]);
+        }
+    }
+
+    fn send(&mut self, port: Option<usize>, message: &[u8]) {
+        let result = match port.and_then(|port| self.conns.get_mut(&port)) {
+            Some(conn) => conn.send(message).map_err(MidiError::from),
+            None => Err(MidiError::NotConnected),
+        };
+        match result {
+            Ok(()) => self.last_error = None,
+            Err(error) => self.on_error(error),
+        }
+    }
+
+    fn on_error(&mut self, error: MidiError) {
+        if self.last_error.as_ref() != Some(&error) {
+            self.new_errors.push(error.clone());
+        }
+        self.last_error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_are_reported_once() {
+        let mut midi_out = MidiOut::new();
+        let note = Note {
+            note_number: 60,
+            velocity: 100,
+        };
+        midi_out.send_note_on(0, &note);
+        midi_out.send_note_off(0, &note);
+        assert_eq!(midi_out.take_errors(), vec![MidiError::NotConnected]);
+
+        midi_out.send_note_on(0, &note);
+        assert_eq!(midi_out.take_errors(), vec![]);
+    }
+
+    #[test]
+    fn test_sounding_notes_are_tracked_per_channel() {
+        let mut midi_out = MidiOut::new();
+        let note = |note_number| Note {
+            note_number,
+            velocity: 100,
+        };
+        midi_out.send_note_on(0, &note(60));
+        midi_out.send_note_on(0, &note(64));
+        midi_out.send_note_off(0, &note(60));
+        midi_out.channel = 2;
+        midi_out.send_note_on(0, &note(60));
+        assert_eq!(
+            midi_out.sounding_notes(),
+            vec![(None, 0, 64), (None, 2, 60)]
+        );
+
+        // a note off on the wrong channel doesn't count
+        midi_out.send_note_off(0, &note(64));
+        assert_eq!(
+            midi_out.sounding_notes(),
+            vec![(None, 0, 64), (None, 2, 60)]
+        );
+
+        midi_out.stop_all_notes();
+        assert_eq!(midi_out.sounding_notes(), vec![]);
+    }
+
+    #[test]
+    fn test_rows_are_routed() {
+        let mut midi_out = MidiOut::new();
+        midi_out.channel = 1;
+        let route = RowRoute {
+            port: None,
+            channel: Some(9),
+        };
+        assert_eq!(midi_out.set_row_route(2, route), Ok(()));
+
+        let note = Note {
+            note_number: 36,
+            velocity: 100,
+        };
+        midi_out.send_note_on(0, &note);
+        midi_out.send_note_on(2, &note);
+        assert_eq!(
+            midi_out.sounding_notes(),
+            vec![(None, 1, 36), (None, 9, 36)]
+        );
+
+        // the row's note off goes to the same place as its note on
+        midi_out.send_note_off(2, &note);
+        assert_eq!(midi_out.sounding_notes(), vec![(None, 1, 36)]);
+    }
+
+    #[test]
+    fn test_removed_rows_lose_their_routes() {
+        let mut midi_out = MidiOut::new();
+        midi_out.channel = 1;
+        let route = RowRoute {
+            port: None,
+            channel: Some(9),
+        };
+        assert_eq!(midi_out.set_row_route(1, route), Ok(()));
+        assert_eq!(midi_out.set_row_route(3, route), Ok(()));
+
+        midi_out.set_num_rows(2);
+        midi_out.set_num_rows(4);
+        assert_eq!(midi_out.destination(1), (None, 9));
+        assert_eq!(midi_out.destination(3), (None, 1));
+        assert_eq!(midi_out.row_routes[2..], [RowRoute::default(); 2]);
+    }
+}
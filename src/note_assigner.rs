@@ -1,8 +1,13 @@
 #![allow(dead_code)]
 
 use crate::looping_state::LoopingSequence;
-use crate::rho_config::NUM_ROWS;
-use std::cmp::PartialOrd;
+use crate::rho_config::DEFAULT_NUM_ROWS;
+use crate::scale::Scale;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::cmp::{PartialOrd, Reverse};
+use std::collections::HashMap;
 use std::fmt;
 
 pub fn to_string(note: &Note) -> String {
@@ -51,25 +56,139 @@ impl fmt::Display for Note {
 }
 
 // How midi notes are assigned to rows
-enum NoteOrdering {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoteOrdering {
     OldestFirst,
+    #[default]
     LowestFirst,
+    HighestFirst,
+    NewestFirst,
+    Random, // shuffled whenever a note is added
+    // the oldest first order read from the end, unlike newest first this keeps the gaps left in
+    // hold mode where they are, so a note that fills a gap doesn't jump to the first row
+    AsPlayedReversed,
+}
+
+impl NoteOrdering {
+    pub const ALL: [NoteOrdering; 6] = [
+        NoteOrdering::OldestFirst,
+        NoteOrdering::LowestFirst,
+        NoteOrdering::HighestFirst,
+        NoteOrdering::NewestFirst,
+        NoteOrdering::Random,
+        NoteOrdering::AsPlayedReversed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteOrdering::OldestFirst => "Oldest First",
+            NoteOrdering::LowestFirst => "Lowest First",
+            NoteOrdering::HighestFirst => "Highest First",
+            NoteOrdering::NewestFirst => "Newest First",
+            NoteOrdering::Random => "Random",
+            NoteOrdering::AsPlayedReversed => "As Played Reversed",
+        }
+    }
 }
 
 // if held notes are pinned to rows, or if changes in held notes reassign rows dynamically
-enum RowAssign {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RowAssign {
+    #[default]
     Dynamic,
     Hold,
 }
 
-enum NoteWrapping {
+impl RowAssign {
+    pub const ALL: [RowAssign; 2] = [RowAssign::Dynamic, RowAssign::Hold];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RowAssign::Dynamic => "Dynamic",
+            RowAssign::Hold => "Hold",
+        }
+    }
+}
+
+// what happens to notes when there are more of them than rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoteWrapping {
     None,
     Wrap,
+    #[default]
     Fold,
     StackHigh,
     StackLow,
 }
 
+impl NoteWrapping {
+    pub const ALL: [NoteWrapping; 5] = [
+        NoteWrapping::None,
+        NoteWrapping::Wrap,
+        NoteWrapping::Fold,
+        NoteWrapping::StackHigh,
+        NoteWrapping::StackLow,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteWrapping::None => "None",
+            NoteWrapping::Wrap => "Wrap",
+            NoteWrapping::Fold => "Fold",
+            NoteWrapping::StackHigh => "Stack High",
+            NoteWrapping::StackLow => "Stack Low",
+        }
+    }
+}
+
+// which way the auto octave fill goes from the held notes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OctaveDirection {
+    #[default]
+    Up,
+    Down,
+    Alternating, // up one, down one, up two...
+}
+
+impl OctaveDirection {
+    pub const ALL: [OctaveDirection; 3] = [
+        OctaveDirection::Up,
+        OctaveDirection::Down,
+        OctaveDirection::Alternating,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OctaveDirection::Up => "Up",
+            OctaveDirection::Down => "Down",
+            OctaveDirection::Alternating => "Alternating",
+        }
+    }
+}
+
+// changes the pitch of what a row plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RowPitch {
+    pub transpose: i32, // semitones
+    pub octave: i32,
+    // the row plays this note instead of the held notes, e.g. for a drum sound
+    pub fixed_note: Option<usize>,
+}
+
+impl RowPitch {
+    // the note a held note is played as
+    pub fn apply(&self, note: Note) -> Note {
+        let note_number = note.note_number as i32 + self.transpose + 12 * self.octave;
+        Note {
+            note_number: note_number.clamp(0, 127) as usize,
+            ..note
+        }
+    }
+}
+
+// the velocity of a fixed note, it can still be changed by the velocity settings
+const FIXED_NOTE_VELOCITY: usize = 100;
+
 // data structure for a single row of the sequencer
 // this could implement an iterator trait, and next does the right things...
 #[derive(Debug)]
@@ -77,6 +196,7 @@ struct Row {
     active: bool,                 // is the row on or off
     notes: LoopingSequence<Note>, // the midi notes associated with the row
     rotation_counter: usize,      // which of notes to play next
+    pitch: RowPitch,
 }
 
 impl Row {
@@ -84,8 +204,41 @@ impl Row {
         self.notes.append(note);
     }
 
-    pub fn tick(&mut self) -> Option<Note> {
-        self.notes.next()
+    // fixed notes aren't quantized, they are usually drums
+    pub fn tick(&mut self, scale: &Scale) -> Option<Note> {
+        if let Some(note_number) = self.pitch.fixed_note {
+            return Some(Note {
+                note_number,
+                velocity: FIXED_NOTE_VELOCITY,
+            });
+        }
+        self.notes
+            .next()
+            .map(|note| self.pitch_in_scale(note, scale))
+    }
+
+    // the notes the row plays, for showing in the gui
+    pub fn notes_to_play(&self, scale: &Scale) -> Vec<Note> {
+        match self.pitch.fixed_note {
+            Some(note_number) => vec![Note {
+                note_number,
+                velocity: FIXED_NOTE_VELOCITY,
+            }],
+            None => self
+                .notes
+                .clone_data()
+                .into_iter()
+                .map(|note| self.pitch_in_scale(note, scale))
+                .collect(),
+        }
+    }
+
+    fn pitch_in_scale(&self, note: Note, scale: &Scale) -> Note {
+        let note = self.pitch.apply(note);
+        Note {
+            note_number: scale.quantize(note.note_number),
+            ..note
+        }
     }
 }
 
@@ -95,6 +248,7 @@ impl Default for Row {
             active: true,
             notes: LoopingSequence::new(vec![]),
             rotation_counter: 0,
+            pitch: RowPitch::default(),
         }
     }
 }
@@ -151,7 +305,9 @@ fn map_note_index_to_row_index(
     active_row_indices: &Vec<usize>,
     note_wrapping_mode: &NoteWrapping,
 ) -> Option<usize> {
-    let active_row_indices = active_row_indices;
+    if active_row_indices.is_empty() {
+        return None;
+    }
     let max_row = active_row_indices.len() - 1;
 
     let row_index = match note_wrapping_mode {
@@ -160,7 +316,7 @@ fn map_note_index_to_row_index(
         NoteWrapping::StackHigh => Some(stack_high(note_index, max_row)),
         NoteWrapping::StackLow => Some(stack_low(note_index, max_row)),
         NoteWrapping::None => {
-            if note_index < max_row {
+            if note_index <= max_row {
                 Some(note_index)
             } else {
                 None
@@ -168,38 +324,51 @@ fn map_note_index_to_row_index(
         }
     };
 
-    if let Some(r) = row_index {
-        Some(active_row_indices[r])
-    } else {
-        None
-    }
+    row_index.map(|r| active_row_indices[r])
 }
 
 // This class keeps track of the active notes, assigns notes to rows, and handles which note comes next for a given row.
 // Probably should be renamed to reflect that fact...
 pub struct NoteAssigner {
     active_notes: Vec<Option<Note>>, // the none state means that we have an empty row but others are pinned above it
-    rows: [Row; NUM_ROWS],
+    rows: Vec<Row>,
     note_ordering_mode: NoteOrdering,
+    row_assign_mode: RowAssign,
     note_wrapping_mode: NoteWrapping,
+    // when each note was played, counting up, for ordering by age
+    note_ages: HashMap<usize, usize>,
+    note_count: usize,
+    rng: StdRng,
 
-    hold_notes_enabled: bool,
     auto_octave_enabled: bool,
+    octave_direction: OctaveDirection,
+    max_octaves: usize, // how far the octave fill goes before it starts again from one octave
     invert_rows_enabled: bool,
+    scale: Scale, // what gets played is moved into this scale
+}
+
+impl Default for NoteAssigner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NoteAssigner {
     pub fn new() -> Self {
-        let rows_vec: Vec<Row> = (0..4).map(|_| Row::default()).collect();
-        let rows_array: [Row; 4] = rows_vec.try_into().unwrap();
         NoteAssigner {
             active_notes: vec![],
-            rows: rows_array,
+            rows: (0..DEFAULT_NUM_ROWS).map(|_| Row::default()).collect(),
             note_ordering_mode: NoteOrdering::LowestFirst,
+            row_assign_mode: RowAssign::Dynamic,
             note_wrapping_mode: NoteWrapping::Fold,
-            hold_notes_enabled: false,
+            note_ages: HashMap::new(),
+            note_count: 0,
+            rng: StdRng::from_entropy(),
             auto_octave_enabled: false,
+            octave_direction: OctaveDirection::Up,
+            max_octaves: 3,
             invert_rows_enabled: false,
+            scale: Scale::default(),
         }
     }
 
@@ -209,7 +378,7 @@ impl NoteAssigner {
         let mut notes = vec![];
         for row_index in triggered_rows {
             if self.row_has_note_and_active(row_index) {
-                if let Some(note) = self.rows[row_index].tick() {
+                if let Some(note) = self.rows[row_index].tick(&self.scale) {
                     notes.push(note);
                 }
             }
@@ -223,24 +392,13 @@ impl NoteAssigner {
             velocity,
         };
 
+        self.note_count += 1;
+        self.note_ages.insert(note_number, self.note_count);
+
+        // in hold mode a new note goes in a gap if there is one, so the other notes stay put
         if !self.fill_empty_note_if_available(new_note) {
-            match self.note_ordering_mode {
-                NoteOrdering::LowestFirst => {
-                    let pos = self
-                        .active_notes
-                        .iter()
-                        .position(|x| x.map_or(false, |x| x > new_note));
-
-                    if let Some(pos) = pos {
-                        self.active_notes.insert(pos, Some(new_note));
-                    } else {
-                        self.active_notes.push(Some(new_note));
-                    }
-                }
-                NoteOrdering::OldestFirst => {
-                    self.active_notes.push(Some(new_note));
-                }
-            }
+            self.active_notes.push(Some(new_note));
+            self.order_notes();
         }
 
         self.update_note_to_row_mapping();
@@ -249,7 +407,9 @@ impl NoteAssigner {
     pub fn note_off(&mut self, note_number: usize) {
         // find the note number and remove it, assume there could be more than one
 
-        if self.hold_notes_enabled {
+        self.note_ages.remove(&note_number);
+
+        if self.row_assign_mode == RowAssign::Hold {
             self.active_notes.iter_mut().for_each(|note| {
                 if note
                     .as_ref()
@@ -271,8 +431,36 @@ impl NoteAssigner {
         self.update_note_to_row_mapping();
     }
 
-    pub fn set_hold_notes_enabled(&mut self, enabled: bool) {
-        self.hold_notes_enabled = enabled;
+    // let go of every held note, e.g. when their note offs would never arrive
+    pub fn release_all_notes(&mut self) {
+        self.note_ages.clear();
+        self.active_notes.clear();
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_note_ordering(&mut self, ordering: NoteOrdering) {
+        self.note_ordering_mode = ordering;
+        self.order_notes();
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_row_assign(&mut self, mode: RowAssign) {
+        self.row_assign_mode = mode;
+        if mode == RowAssign::Dynamic {
+            // nothing is pinned any more, so close up the gaps
+            self.active_notes.retain(Option::is_some);
+            self.update_note_to_row_mapping();
+        }
+    }
+
+    pub fn set_note_wrapping(&mut self, wrapping: NoteWrapping) {
+        self.note_wrapping_mode = wrapping;
+        self.update_note_to_row_mapping();
+    }
+
+    // makes the random ordering repeatable
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
     }
 
     pub fn all_active_notes_empty(&self) -> bool {
@@ -280,12 +468,14 @@ impl NoteAssigner {
     }
 
     pub fn row_has_note_and_active(&self, index: usize) -> bool {
-        index < NUM_ROWS && self.rows[index].active && self.rows[index].notes.len() > 0
+        index < self.rows.len()
+            && self.rows[index].active
+            && (self.rows[index].notes.len() > 0 || self.rows[index].pitch.fixed_note.is_some())
     }
 
     pub fn set_row_active(&mut self, row_number: usize, active: bool) {
-        if row_number < NUM_ROWS {
-            self.rows[row_number].active = active;
+        if let Some(row) = self.rows.get_mut(row_number) {
+            row.active = active;
         }
     }
 
@@ -308,7 +498,45 @@ impl NoteAssigner {
     }
 
     pub fn row_is_active(&self, index: usize) -> bool {
-        index < NUM_ROWS && self.rows[index].active
+        self.rows.get(index).map_or(false, |row| row.active)
+    }
+
+    pub fn set_row_pitch(&mut self, row_number: usize, pitch: RowPitch) {
+        if let Some(row) = self.rows.get_mut(row_number) {
+            row.pitch = pitch;
+            // a fixed note row doesn't take any of the held notes
+            self.update_note_to_row_mapping();
+        }
+    }
+
+    // new rows start active with no pitch changes, the held notes are spread over them again
+    pub fn set_num_rows(&mut self, num_rows: usize) {
+        self.rows.resize_with(num_rows, Row::default);
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    // for notes that are changed after they leave the row, so they stay in the scale too
+    pub fn quantize_for_row(&self, row_number: usize, note_number: usize) -> usize {
+        let fixed = self
+            .rows
+            .get(row_number)
+            .map_or(false, |row| row.pitch.fixed_note.is_some());
+        if fixed {
+            return note_number;
+        }
+        self.scale.quantize(note_number)
+    }
+
+    // the active rows that play held notes
+    fn assignable_row_indices(&self) -> Vec<usize> {
+        self.active_row_indices()
+            .into_iter()
+            .filter(|&i| self.rows[i].pitch.fixed_note.is_none())
+            .collect()
     }
 
     // "private" stuff
@@ -317,40 +545,71 @@ impl NoteAssigner {
     // e.g. if row 1 & 2 are active then 2 becomes 1 and 1 becomes 2, 3 & 4 are left alone
     fn invert_active_row_index(&self, index: usize) -> usize {
         *self
-            .active_row_indices()
+            .assignable_row_indices()
             .iter()
             .rev()
             .nth(index)
             .unwrap_or(&index)
     }
 
+    // put the active notes in order, any gaps stay where they are
+    fn order_notes(&mut self) {
+        let mut notes: Vec<Note> = self.active_notes.iter().flatten().copied().collect();
+        let age = |note: &Note| self.note_ages.get(&note.note_number).copied();
+        match self.note_ordering_mode {
+            NoteOrdering::LowestFirst => notes.sort_by_key(|n| n.note_number),
+            NoteOrdering::HighestFirst => notes.sort_by_key(|n| Reverse(n.note_number)),
+            // as played is stored oldest first and reversed when assigning rows
+            NoteOrdering::OldestFirst | NoteOrdering::AsPlayedReversed => notes.sort_by_key(age),
+            NoteOrdering::NewestFirst => notes.sort_by_key(|n| Reverse(age(n))),
+            NoteOrdering::Random => notes.shuffle(&mut self.rng),
+        }
+
+        let mut notes = notes.into_iter();
+        for note in self.active_notes.iter_mut().filter(|n| n.is_some()) {
+            *note = notes.next();
+        }
+    }
+
+    // the active notes in the order they are assigned to rows
+    fn ordered_notes(&self) -> Vec<Option<Note>> {
+        let mut notes = self.active_notes.clone();
+        if self.note_ordering_mode == NoteOrdering::AsPlayedReversed {
+            notes.reverse();
+        }
+        notes
+    }
+
+    // the active rows in the order that notes are assigned to them
+    fn row_order(&self) -> Vec<usize> {
+        if self.invert_rows_enabled {
+            (0..self.assignable_row_indices().len())
+                .map(|i| self.invert_active_row_index(i))
+                .collect()
+        } else {
+            self.assignable_row_indices()
+        }
+    }
+
     // try to find an unassigned row to assign a note to, if can't return false
     // this active note thing sucks...
     fn fill_empty_note_if_available(&mut self, note: Note) -> bool {
         // todo there could be multiple empty rows, in which case we should respect the NoteOrdering
         // perhaps
-        let pos = self.active_notes.iter().position(|n| match n {
-            None => true,
-            Some(_) => false,
-        });
+        let pos = self.active_notes.iter().position(|n| n.is_none());
 
         // if Some(pos) then we found an empty slot
 
         match pos {
             Some(pos) => {
                 self.active_notes[pos] = Some(note);
-                return true;
-            }
-            None => {
-                return false;
+                true
             }
+            None => false,
         }
     }
     pub fn wrap_notes_enabled(&self) -> bool {
-        match self.note_wrapping_mode {
-            NoteWrapping::None => false,
-            _ => true,
-        }
+        !matches!(self.note_wrapping_mode, NoteWrapping::None)
     }
 
     // when anything changes, reassign the notes to the rows
@@ -358,7 +617,7 @@ impl NoteAssigner {
         self.clear_all_note_assignments();
 
         // make a copy of active notes, because we can't borrow self.active_notes to change self.rows
-        let active_notes = self.active_notes.clone();
+        let active_notes = self.ordered_notes();
         // loop over active notes
         // get the row index that the note will be assigned to
         // copy the note in
@@ -368,7 +627,7 @@ impl NoteAssigner {
             .for_each(|(note_index, note)| {
                 let row_index = map_note_index_to_row_index(
                     note_index,
-                    &self.active_row_indices(),
+                    &self.row_order(),
                     &self.note_wrapping_mode,
                 );
                 if let Some(r) = row_index {
@@ -385,60 +644,103 @@ impl NoteAssigner {
         //self.wrap_note_rotation_counters();
     }
 
+    // the first note goes to the top row instead of the bottom one
+    pub fn set_invert_rows_enabled(&mut self, enabled: bool) {
+        self.invert_rows_enabled = enabled;
+        self.update_note_to_row_mapping();
+    }
+
     pub fn set_fill_octaves_enabled(&mut self, enabled: bool) {
         self.auto_octave_enabled = enabled;
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_octave_direction(&mut self, direction: OctaveDirection) {
+        self.octave_direction = direction;
+        self.update_note_to_row_mapping();
+    }
+
+    pub fn set_max_octaves(&mut self, max_octaves: usize) {
+        self.max_octaves = max_octaves.max(1);
+        self.update_note_to_row_mapping();
     }
 
-    // this fills any notes that don't have notes with octaves above the ones that do
+    // this fills any active rows that don't have notes with octaves of the ones that do
     fn fill_remaining_rows_with_octaves(&mut self) {
-        // TODO
+        let mut fill_index = 0;
+        for row_index in self.row_order() {
+            if self.rows[row_index].notes.len() > 0 {
+                continue;
+            }
+            if let Some(note) = self.get_octave_shifted_note_for_index(fill_index) {
+                self.rows[row_index].add_note(note);
+            }
+            fill_index += 1;
+        }
     }
 
-    fn get_octave_shifted_note_for_index(&self, row_index: usize) -> Note {
-        // TODO this mod might be wrong or weird if rows are deactivated
-        if self.active_notes.is_empty() {
-            return Note {
-                note_number: 0,
-                velocity: 0,
-            };
+    // the held notes repeat over the filled rows, shifted by another octave each time round.
+    // The shift goes from 1 up to the max octaves and starts again, it's never 0 as that would
+    // just play the held notes again
+    fn get_octave_shifted_note_for_index(&self, fill_index: usize) -> Option<Note> {
+        let held_notes: Vec<Note> = self.ordered_notes().into_iter().flatten().collect();
+        if held_notes.is_empty() {
+            return None;
         }
-        let note_to_repeat = self.active_notes[row_index % self.active_notes.len()];
-        let octave = row_index / self.active_notes.len();
+        let note_to_repeat = held_notes[fill_index % held_notes.len()];
+        let octaves = 1 + (fill_index / held_notes.len()) % self.max_octaves;
+
+        let shift = match self.octave_direction {
+            OctaveDirection::Up => octaves as i32,
+            OctaveDirection::Down => -(octaves as i32),
+            OctaveDirection::Alternating if octaves % 2 == 1 => (octaves as i32 + 1) / 2,
+            OctaveDirection::Alternating => -(octaves as i32 / 2),
+        };
 
-        Note {
-            note_number: note_to_repeat.unwrap().note_number + 12 * octave,
-            velocity: note_to_repeat.unwrap().velocity,
+        // keep it inside the midi note range by going the other way instead
+        let mut note_number = note_to_repeat.note_number as i32 + 12 * shift;
+        if !(0..=127).contains(&note_number) {
+            note_number = note_to_repeat.note_number as i32 - 12 * shift;
+        }
+        while note_number < 0 {
+            note_number += 12;
+        }
+        while note_number > 127 {
+            note_number -= 12;
         }
+
+        Some(Note {
+            note_number: note_number as usize,
+            velocity: note_to_repeat.velocity,
+        })
     }
 
     // return an array of the notes assigned to each row
     const EMPTY_VEC: Vec<Note> = Vec::new();
-    pub fn get_notes_for_rows(&self) -> [Vec<Note>; NUM_ROWS] {
-        let mut notes: [Vec<Note>; NUM_ROWS] = Default::default();
-        for (i, row) in self.rows.iter().enumerate() {
-            notes[i] = row.notes.clone_data();
-        }
-        notes
+    pub fn get_notes_for_rows(&self) -> Vec<Vec<Note>> {
+        self.rows
+            .iter()
+            .map(|row| row.notes_to_play(&self.scale))
+            .collect()
     }
 
     pub fn print_row_notes(&self) {
         print!("[");
-        let mut i = 0;
-        for row in self.get_notes_for_rows() {
+        for (i, row) in self.get_notes_for_rows().iter().enumerate() {
             print!("[{} ", i);
             for note in row {
                 print!("{}, ", note);
             }
-            i += 1;
             print!("] ");
         }
-        print!("]\n");
+        println!("]");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scale::ScaleKind;
 
     #[test]
     fn it_wraps() {
@@ -482,7 +784,7 @@ mod tests {
         ga.note_off(70);
         assert!(ga.active_notes.is_empty());
 
-        ga.hold_notes_enabled = true;
+        ga.set_row_assign(RowAssign::Hold);
 
         ga.note_on(69, 100);
 
@@ -573,16 +875,360 @@ mod tests {
         assert_eq!(ga.active_row_indices(), vec![1]);
     }
 
-    // #[test]
-    // fn test_fill_octaves() {
-    //     let mut ga = NoteAssigner::new();
+    // the note numbers assigned to each row
+    fn row_note_numbers(ga: &NoteAssigner) -> Vec<Vec<usize>> {
+        ga.get_notes_for_rows()
+            .iter()
+            .map(|notes| notes.iter().map(|n| n.note_number).collect())
+            .collect()
+    }
+
+    fn play_notes(ga: &mut NoteAssigner, notes: &[usize]) {
+        for &note in notes {
+            ga.note_on(note, 100);
+        }
+    }
+
+    #[test]
+    fn test_note_orderings() {
+        let expected = [
+            (NoteOrdering::OldestFirst, [64, 60, 67]),
+            (NoteOrdering::LowestFirst, [60, 64, 67]),
+            (NoteOrdering::HighestFirst, [67, 64, 60]),
+            (NoteOrdering::NewestFirst, [67, 60, 64]),
+            (NoteOrdering::AsPlayedReversed, [67, 60, 64]),
+        ];
+        for (ordering, expected) in expected {
+            let mut ga = NoteAssigner::new();
+            ga.set_note_ordering(ordering);
+            play_notes(&mut ga, &[64, 60, 67]);
+            let rows = row_note_numbers(&ga);
+            assert_eq!(
+                rows,
+                vec![
+                    vec![expected[0]],
+                    vec![expected[1]],
+                    vec![expected[2]],
+                    vec![]
+                ],
+                "{:?}",
+                ordering
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_ordering_is_repeatable() {
+        let play = |seed| {
+            let mut ga = NoteAssigner::new();
+            ga.set_note_ordering(NoteOrdering::Random);
+            ga.set_random_seed(seed);
+            play_notes(&mut ga, &[60, 62, 64, 65]);
+            row_note_numbers(&ga)
+        };
+        let rows = play(3);
+        assert_eq!(rows, play(3));
+
+        // every note is still there once
+        let mut notes: Vec<usize> = rows.concat();
+        notes.sort();
+        assert_eq!(notes, vec![60, 62, 64, 65]);
+    }
+
+    #[test]
+    fn test_changing_ordering_reorders_held_notes() {
+        let mut ga = NoteAssigner::new();
+        play_notes(&mut ga, &[64, 60, 67]);
+        assert_eq!(row_note_numbers(&ga)[0], vec![60]);
+
+        // the order they were played in is remembered
+        ga.set_note_ordering(NoteOrdering::OldestFirst);
+        assert_eq!(row_note_numbers(&ga)[0], vec![64]);
+        ga.set_note_ordering(NoteOrdering::HighestFirst);
+        assert_eq!(row_note_numbers(&ga)[0], vec![67]);
+    }
+
+    #[test]
+    fn test_note_wrapping() {
+        let expected = [
+            (
+                NoteWrapping::None,
+                vec![vec![60], vec![62], vec![64], vec![65]],
+            ),
+            (
+                NoteWrapping::Wrap,
+                vec![vec![60, 67], vec![62, 69], vec![64], vec![65]],
+            ),
+            (
+                NoteWrapping::Fold,
+                vec![vec![60], vec![62], vec![64, 69], vec![65, 67]],
+            ),
+            (
+                NoteWrapping::StackHigh,
+                vec![vec![60], vec![62], vec![64], vec![65, 67, 69]],
+            ),
+            (
+                NoteWrapping::StackLow,
+                vec![vec![60, 67, 69], vec![62], vec![64], vec![65]],
+            ),
+        ];
+        for (wrapping, expected) in expected {
+            let mut ga = NoteAssigner::new();
+            ga.set_note_wrapping(wrapping);
+            play_notes(&mut ga, &[60, 62, 64, 65, 67, 69]);
+            assert_eq!(row_note_numbers(&ga), expected, "{:?}", wrapping);
+        }
+    }
+
+    #[test]
+    fn test_hold_keeps_notes_on_their_rows() {
+        for ordering in NoteOrdering::ALL {
+            let mut ga = NoteAssigner::new();
+            ga.set_note_ordering(ordering);
+            ga.set_random_seed(1);
+            ga.set_row_assign(RowAssign::Hold);
+            play_notes(&mut ga, &[64, 60, 67]);
+            let before = row_note_numbers(&ga);
+
+            // letting go of a note leaves its row empty and the others where they were
+            let released = before[1][0];
+            ga.note_off(released);
+            let mut expected = before.clone();
+            expected[1].clear();
+            assert_eq!(row_note_numbers(&ga), expected, "{:?}", ordering);
+
+            // a new note fills the gap
+            ga.note_on(72, 100);
+            expected[1] = vec![72];
+            assert_eq!(row_note_numbers(&ga), expected, "{:?}", ordering);
+
+            // going back to dynamic closes the gaps up
+            ga.note_off(72);
+            ga.set_row_assign(RowAssign::Dynamic);
+            assert_eq!(ga.active_notes.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_release_all_notes() {
+        let mut ga = NoteAssigner::new();
+        ga.set_row_assign(RowAssign::Hold);
+        play_notes(&mut ga, &[64, 60, 67]);
+        ga.note_off(60);
+
+        ga.release_all_notes();
+        assert_eq!(row_note_numbers(&ga), vec![vec![]; 4]);
+
+        // the rows fill up from the top again
+        ga.note_on(72, 100);
+        assert_eq!(row_note_numbers(&ga)[0], vec![72]);
+    }
+
+    #[test]
+    fn test_all_mode_combinations() {
+        for ordering in NoteOrdering::ALL {
+            for row_assign in RowAssign::ALL {
+                for wrapping in NoteWrapping::ALL {
+                    let mut ga = NoteAssigner::new();
+                    ga.set_note_ordering(ordering);
+                    ga.set_row_assign(row_assign);
+                    ga.set_note_wrapping(wrapping);
+                    ga.set_random_seed(7);
+                    play_notes(&mut ga, &[60, 67, 64, 62, 65, 69]);
+                    ga.note_off(64);
+                    let mode = format!("{:?} {:?} {:?}", ordering, row_assign, wrapping);
+
+                    // each held note is on one row, unless there are too many and they are dropped
+                    let mut notes = row_note_numbers(&ga).concat();
+                    notes.sort();
+                    if wrapping == NoteWrapping::None {
+                        assert!(notes.len() <= DEFAULT_NUM_ROWS, "{}", mode);
+                        assert!(!notes.contains(&64), "{}", mode);
+                    } else {
+                        assert_eq!(notes, vec![60, 62, 65, 67, 69], "{}", mode);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invert_rows() {
+        let mut ga = NoteAssigner::new();
+        ga.set_row_active(1, false);
+        play_notes(&mut ga, &[60, 64]);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![], vec![64], vec![]]
+        );
+
+        // only the active rows swap round
+        ga.set_invert_rows_enabled(true);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![], vec![], vec![64], vec![60]]
+        );
+
+        // octaves fill downwards from the top too
+        ga.note_off(64);
+        ga.set_fill_octaves_enabled(true);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![84], vec![], vec![72], vec![60]]
+        );
+    }
 
-    //     ga.note_on(60, 100);
-    //     ga.fill_remaining_rows_with_octaves();
+    #[test]
+    fn test_row_pitch() {
+        let mut ga = NoteAssigner::new();
+        play_notes(&mut ga, &[60, 64, 67]);
+        ga.set_row_pitch(
+            1,
+            RowPitch {
+                transpose: 2,
+                octave: -1,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![54], vec![67], vec![]]
+        );
+        assert_eq!(ga.get_next_notes(vec![1])[0].note_number, 54);
+
+        // a fixed note row is skipped when assigning the held notes
+        ga.set_row_pitch(
+            0,
+            RowPitch {
+                fixed_note: Some(36),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![36], vec![50], vec![64], vec![67]]
+        );
+
+        // and plays without any held notes
+        for note in [60, 64, 67] {
+            ga.note_off(note);
+        }
+        assert!(ga.row_has_note_and_active(0));
+        assert!(!ga.row_has_note_and_active(1));
+        assert_eq!(ga.get_next_notes(vec![0, 1])[0].note_number, 36);
+    }
 
-    //     assert_eq!(ga.rows[0].notes.clone_data()[0].note_number, 60);
-    //     assert_eq!(ga.rows[1].notes.clone_data()[0].note_number, 72);
-    //     assert_eq!(ga.rows[2].notes.clone_data()[0].note_number, 84);
-    //     assert_eq!(ga.rows[3].notes.clone_data()[0].note_number, 96);
-    // }
+    #[test]
+    fn test_scale() {
+        let mut ga = NoteAssigner::new();
+        play_notes(&mut ga, &[60, 61]);
+        ga.set_row_pitch(
+            0,
+            RowPitch {
+                transpose: 1,
+                ..Default::default()
+            },
+        );
+        ga.set_row_pitch(
+            2,
+            RowPitch {
+                fixed_note: Some(37),
+                ..Default::default()
+            },
+        );
+        ga.set_scale(Scale {
+            kind: ScaleKind::Major,
+            ..Default::default()
+        });
+        // the held notes and the transposed ones are moved into the scale, but fixed notes aren't
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![60], vec![37], vec![]]
+        );
+        assert_eq!(ga.get_next_notes(vec![0])[0].note_number, 60);
+    }
+
+    #[test]
+    fn test_fill_octaves() {
+        let mut ga = NoteAssigner::new();
+
+        ga.note_on(60, 100);
+        ga.set_fill_octaves_enabled(true);
+
+        assert_eq!(ga.rows[0].notes.clone_data()[0].note_number, 60);
+        assert_eq!(ga.rows[1].notes.clone_data()[0].note_number, 72);
+        assert_eq!(ga.rows[2].notes.clone_data()[0].note_number, 84);
+        assert_eq!(ga.rows[3].notes.clone_data()[0].note_number, 96);
+
+        // the held notes take turns
+        ga.note_on(64, 100);
+        assert_eq!(
+            row_note_numbers(&ga),
+            vec![vec![60], vec![64], vec![72], vec![76]]
+        );
+
+        ga.set_fill_octaves_enabled(false);
+        assert_eq!(row_note_numbers(&ga)[2], vec![]);
+    }
+
+    #[test]
+    fn test_fill_octave_directions() {
+        let expected = [
+            (OctaveDirection::Up, 1, [60, 72, 72, 72]),
+            (OctaveDirection::Down, 3, [60, 48, 36, 24]),
+            (OctaveDirection::Alternating, 3, [60, 72, 48, 84]),
+            (OctaveDirection::Alternating, 2, [60, 72, 48, 72]),
+        ];
+        for (direction, max_octaves, expected) in expected {
+            let mut ga = NoteAssigner::new();
+            ga.set_fill_octaves_enabled(true);
+            ga.set_octave_direction(direction);
+            ga.set_max_octaves(max_octaves);
+            ga.note_on(60, 100);
+            let rows: Vec<usize> = row_note_numbers(&ga).concat();
+            assert_eq!(rows, expected, "{:?} {}", direction, max_octaves);
+        }
+
+        // notes don't go out of the midi range
+        let mut ga = NoteAssigner::new();
+        ga.set_fill_octaves_enabled(true);
+        ga.note_on(120, 100);
+        assert_eq!(row_note_numbers(&ga).concat(), vec![120, 108, 96, 84]);
+    }
+
+    #[test]
+    fn test_filled_rows_never_repeat_a_held_note() {
+        for direction in [
+            OctaveDirection::Up,
+            OctaveDirection::Down,
+            OctaveDirection::Alternating,
+        ] {
+            for max_octaves in 1..=4 {
+                for row_assign in [RowAssign::Dynamic, RowAssign::Hold] {
+                    let mut ga = NoteAssigner::new();
+                    ga.set_num_rows(8);
+                    ga.set_row_assign(row_assign);
+                    ga.set_fill_octaves_enabled(true);
+                    ga.set_octave_direction(direction);
+                    ga.set_max_octaves(max_octaves);
+                    play_notes(&mut ga, &[60, 64, 67]);
+                    // in hold mode this leaves a gap below the other held notes
+                    ga.note_off(64);
+
+                    let held = [60, 67];
+                    let rows = row_note_numbers(&ga);
+                    let filled = rows.iter().filter(|notes| !held.contains(&notes[0]));
+                    assert_eq!(
+                        filled.count(),
+                        6,
+                        "{:?} {} {:?} {:?}",
+                        direction,
+                        max_octaves,
+                        row_assign,
+                        rows
+                    );
+                }
+            }
+        }
+    }
 }
@@ -5,6 +5,12 @@ pub struct Phasor {
     phase_inc: f32,
 }
 
+impl Default for Phasor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Phasor {
     pub fn new() -> Self {
         Phasor {
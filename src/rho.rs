@@ -1,17 +1,122 @@
 extern crate rand;
 
+use crate::groove;
+use crate::groove::Groove;
 use crate::looping_state;
 use crate::note_assigner;
 use crate::note_assigner::Note;
-use crate::note_assigner::NoteAssigner;
-use crate::rho_config::NUM_ROWS;
+use crate::note_assigner::{
+    NoteAssigner, NoteOrdering, NoteWrapping, OctaveDirection, RowAssign, RowPitch,
+};
+use crate::rho_config::{DEFAULT_NUM_ROWS, MAX_ROWS, PULSES_PER_STEP};
+use crate::scale::Scale;
+use crate::step::{Ratchet, Step, StepGate, StepParams, TrigCondition};
+use crate::velocity;
+use crate::velocity::{RowVelocity, VelocityCurve};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-pub type Rows = [looping_state::LoopingSequence<bool>; NUM_ROWS];
+pub type Rows = Vec<looping_state::LoopingSequence<Step>>;
+
+// the velocity of notes before any accents are applied
+const DEFAULT_VELOCITY: usize = 0x64;
+
+// how fast a row steps, relative to the master clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RowRate {
+    Quarter,
+    Third,
+    Half,
+    #[default]
+    Normal,
+    Triplet,
+    Double,
+    Triple,
+    Quadruple,
+}
+
+impl RowRate {
+    pub const ALL: [RowRate; 8] = [
+        RowRate::Quarter,
+        RowRate::Third,
+        RowRate::Half,
+        RowRate::Normal,
+        RowRate::Triplet,
+        RowRate::Double,
+        RowRate::Triple,
+        RowRate::Quadruple,
+    ];
+
+    // how many clock pulses each step of the row lasts
+    pub fn pulses_per_step(&self) -> usize {
+        match self {
+            RowRate::Quarter => PULSES_PER_STEP * 4,
+            RowRate::Third => PULSES_PER_STEP * 3,
+            RowRate::Half => PULSES_PER_STEP * 2,
+            RowRate::Normal => PULSES_PER_STEP,
+            // three steps in the time of two
+            RowRate::Triplet => PULSES_PER_STEP * 2 / 3,
+            RowRate::Double => PULSES_PER_STEP / 2,
+            RowRate::Triple => PULSES_PER_STEP / 3,
+            RowRate::Quadruple => PULSES_PER_STEP / 4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RowRate::Quarter => "1/4",
+            RowRate::Third => "1/3",
+            RowRate::Half => "1/2",
+            RowRate::Normal => "x1",
+            RowRate::Triplet => "x3/2",
+            RowRate::Double => "x2",
+            RowRate::Triple => "x3",
+            RowRate::Quadruple => "x4",
+        }
+    }
+}
+
+// a note with the clock pulses when it should start and stop
+#[derive(Debug, Clone, Copy)]
+struct ScheduledNote {
+    note: Note,
+    row: usize,
+    on_pulse: usize,
+    off_pulse: Option<usize>, // none if the note is tied to the next note in the row
+}
+
+// the notes to stop and start on a clock pulse, with the row each one is from
+#[derive(Debug, Default)]
+pub struct PulseNotes {
+    pub note_offs: Vec<(usize, Note)>,
+    pub note_ons: Vec<(usize, Note)>,
+}
 
 pub struct Rho {
     note_assigner: NoteAssigner,
     row_loopers: Rows,
-    playing_notes: Vec<Note>,
+    row_rates: Vec<RowRate>,
+    pending_notes: Vec<ScheduledNote>, // notes that have been triggered but are delayed
+    playing_notes: Vec<ScheduledNote>,
+    pulse_counter: usize, // clock pulses since the start
+    swing: f32,
+    groove: Groove,
+    gate_length: f32,                   // as a fraction of a step
+    row_gate_lengths: Vec<Option<f32>>, // overrides the global gate length
+    velocity_curve: VelocityCurve,
+    fixed_velocity: Option<usize>, // used instead of the velocity of the held notes
+    row_velocities: Vec<RowVelocity>,
+    row_loop_counts: Vec<usize>, // how many times each row has started from its first step
+    // whether the last step with a condition played, for the previous condition
+    previous_condition_met: Vec<bool>,
+    fill: bool,
+    rng: StdRng, // for the step probabilities
+}
+
+impl Default for Rho {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Rho {
@@ -19,8 +124,22 @@ impl Rho {
         const DEFAULT_STEP_LEN: usize = 4;
         Rho {
             note_assigner: NoteAssigner::new(),
-            row_loopers: Default::default(),
+            row_loopers: (0..DEFAULT_NUM_ROWS).map(|_| Default::default()).collect(),
+            row_rates: vec![RowRate::Normal; DEFAULT_NUM_ROWS],
+            pending_notes: vec![],
             playing_notes: vec![],
+            pulse_counter: 0,
+            swing: 50.0,
+            groove: Groove::straight(),
+            gate_length: 0.5,
+            row_gate_lengths: vec![None; DEFAULT_NUM_ROWS],
+            velocity_curve: VelocityCurve::Linear,
+            fixed_velocity: None,
+            row_velocities: vec![RowVelocity::default(); DEFAULT_NUM_ROWS],
+            row_loop_counts: vec![0; DEFAULT_NUM_ROWS],
+            previous_condition_met: vec![false; DEFAULT_NUM_ROWS],
+            fill: false,
+            rng: StdRng::from_entropy(),
         }
     }
 
@@ -28,21 +147,84 @@ impl Rho {
         self.note_assigner.set_fill_octaves_enabled(enabled);
     }
 
-    pub fn set_row_activations(&mut self, row_activations: [Vec<bool>; NUM_ROWS]) {
-        for i in 0..NUM_ROWS {
+    pub fn set_invert_rows_enabled(&mut self, enabled: bool) {
+        self.note_assigner.set_invert_rows_enabled(enabled);
+    }
+
+    pub fn set_octave_fill(
+        &mut self,
+        enabled: bool,
+        direction: OctaveDirection,
+        max_octaves: usize,
+    ) {
+        self.note_assigner.set_octave_direction(direction);
+        self.note_assigner.set_max_octaves(max_octaves);
+        self.note_assigner.set_fill_octaves_enabled(enabled);
+    }
+
+    pub fn set_row_activations(&mut self, row_activations: Vec<Vec<Step>>) {
+        for (row_looper, activations) in self.row_loopers.iter_mut().zip(row_activations.iter()) {
             // if the length changes, we need to resize the row looper
-            if self.row_loopers[i].len() != row_activations[i].len() {
-                self.row_loopers[i].resize(row_activations[i].len(), false);
+            if row_looper.len() != activations.len() {
+                row_looper.resize(activations.len(), Step::default());
             }
             // set each step
-            for (j, active) in row_activations[i].iter().enumerate() {
-                self.row_loopers[i].set_step(j, *active);
+            for (j, step) in activations.iter().enumerate() {
+                row_looper.set_step(j, *step);
             }
         }
     }
 
-    pub fn set_hold_notes_enabled(&mut self, enabled: bool) {
-        self.note_assigner.set_hold_notes_enabled(enabled);
+    pub fn set_row_rate(&mut self, row: usize, rate: RowRate) {
+        if let Some(row_rate) = self.row_rates.get_mut(row) {
+            *row_rate = rate;
+        }
+    }
+
+    // swing is the percentage of a pair of steps taken by the first step, 50% is straight
+    pub fn set_swing(&mut self, swing: f32) {
+        self.swing = swing;
+    }
+
+    pub fn set_groove(&mut self, groove: Groove) {
+        self.groove = groove;
+    }
+
+    // the gate length of rows that don't have their own, as a fraction of a step
+    pub fn set_gate_length(&mut self, length: f32) {
+        self.gate_length = length;
+    }
+
+    pub fn set_row_gate_length(&mut self, row: usize, length: Option<f32>) {
+        if let Some(row_gate_length) = self.row_gate_lengths.get_mut(row) {
+            *row_gate_length = length;
+        }
+    }
+
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.velocity_curve = curve;
+    }
+
+    pub fn set_fixed_velocity(&mut self, velocity: Option<usize>) {
+        self.fixed_velocity = velocity;
+    }
+
+    pub fn set_row_velocity(&mut self, row: usize, velocity: RowVelocity) {
+        if let Some(row_velocity) = self.row_velocities.get_mut(row) {
+            *row_velocity = velocity;
+        }
+    }
+
+    pub fn set_note_ordering(&mut self, ordering: NoteOrdering) {
+        self.note_assigner.set_note_ordering(ordering);
+    }
+
+    pub fn set_row_assign(&mut self, mode: RowAssign) {
+        self.note_assigner.set_row_assign(mode);
+    }
+
+    pub fn set_note_wrapping(&mut self, wrapping: NoteWrapping) {
+        self.note_assigner.set_note_wrapping(wrapping);
     }
 
     pub fn note_on(&mut self, note: usize, velocity: usize) {
@@ -55,64 +237,321 @@ impl Rho {
         self.note_assigner.print_row_notes();
     }
 
-    pub fn get_notes_for_rows(&self) -> [Vec<Note>; NUM_ROWS] {
+    // forget the held notes, the caller should stop the playing notes first
+    pub fn release_all_notes(&mut self) {
+        self.note_assigner.release_all_notes();
+    }
+
+    // go back to the start of all the rows
+    pub fn reset(&mut self) {
+        self.pulse_counter = 0;
+        for row in self.row_loopers.iter_mut() {
+            row.reset();
+        }
+        self.row_loop_counts.fill(0);
+        self.previous_condition_met.fill(false);
+    }
+
+    pub fn set_row_pitch(&mut self, row: usize, pitch: RowPitch) {
+        self.note_assigner.set_row_pitch(row, pitch);
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.note_assigner.set_scale(scale);
+    }
+
+    // while fill is on, steps with the fill condition play
+    pub fn set_fill(&mut self, fill: bool) {
+        self.fill = fill;
+    }
+
+    // makes the step probabilities repeatable
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // jump to a clock pulse, e.g. after a song position message. Each row will next play the
+    // step at or after the position
+    pub fn set_pulse_position(&mut self, pulse: usize) {
+        self.pulse_counter = pulse;
+        for (i, (row, rate)) in self
+            .row_loopers
+            .iter_mut()
+            .zip(self.row_rates.iter())
+            .enumerate()
+        {
+            let steps = pulse.div_ceil(rate.pulses_per_step());
+            row.set_position(steps);
+            // the count goes up when the row gets to its first step
+            self.row_loop_counts[i] = if row.len() == 0 { 0 } else { steps / row.len() };
+        }
+    }
+
+    pub fn get_pulse_position(&self) -> usize {
+        self.pulse_counter
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.row_loopers.len()
+    }
+
+    // rows are added or removed at the end, the rows that are kept keep all their settings.
+    // The caller should stop the playing notes first
+    pub fn set_num_rows(&mut self, num_rows: usize) {
+        let num_rows = num_rows.clamp(1, MAX_ROWS);
+        self.row_loopers.resize_with(num_rows, Default::default);
+        self.row_rates.resize(num_rows, RowRate::Normal);
+        self.row_gate_lengths.resize(num_rows, None);
+        self.row_velocities.resize(num_rows, RowVelocity::default());
+        self.row_loop_counts.resize(num_rows, 0);
+        self.previous_condition_met.resize(num_rows, false);
+        self.pending_notes.retain(|n| n.row < num_rows);
+        self.note_assigner.set_num_rows(num_rows);
+    }
+
+    pub fn get_notes_for_rows(&self) -> Vec<Vec<Note>> {
         self.note_assigner.get_notes_for_rows()
     }
 
-    pub fn on_clock_high(&mut self) -> Vec<note_assigner::Note> {
+    // called for every clock pulse, rows step when a whole number of their steps have passed
+    pub fn on_clock_pulse(&mut self) -> PulseNotes {
         // get the rows that are triggered by ticking the row loopers
         let triggered_rows = self.tick_rows();
 
-        let notes_to_play = self.note_assigner.get_next_notes(triggered_rows);
+        for row in triggered_rows {
+            let looper = &self.row_loopers[row];
+            let params = looper
+                .get_step(looper.get_current_step())
+                .map_or(StepParams::default(), |s| s.params);
+            let notes_to_play = self.note_assigner.get_next_notes(vec![row]);
+            if notes_to_play.is_empty() {
+                self.end_ties(row);
+            }
+            self.schedule_notes(row, &params, &notes_to_play);
+        }
 
-        self.track_midi_notes(notes_to_play.clone());
+        // starting notes can stop tied notes, so work out what starts first
+        let notes_to_start = self.notes_to_start();
+        let pulse_notes = PulseNotes {
+            note_offs: self.notes_to_stop(),
+            note_ons: notes_to_start.iter().map(|n| (n.row, n.note)).collect(),
+        };
+        self.playing_notes.extend(notes_to_start);
 
-        // keep track of the midi notes
-        notes_to_play
+        self.pulse_counter += 1;
+        pulse_notes
     }
 
-    pub fn on_clock_low(&mut self) -> Vec<note_assigner::Note> {
-        // send note offs for all the notes
-        let notes_to_stop = self.playing_notes.clone();
+    // stop all the playing notes, e.g. when the transport stops
+    pub fn stop_all_notes(&mut self) -> Vec<(usize, Note)> {
+        let notes_to_stop = self.playing_notes.iter().map(|n| (n.row, n.note)).collect();
         self.playing_notes.clear();
+        self.pending_notes.clear();
         notes_to_stop
     }
 
-    pub fn get_playing_steps(&self) -> [Option<usize>; NUM_ROWS] {
-        let mut steps = [None; NUM_ROWS];
-        let mut i = 0;
-        for row in self.row_loopers.iter() {
-            if self.note_assigner.row_is_active(i) {
-                steps[i] = Some(row.get_current_step());
+    pub fn get_playing_steps(&self) -> Vec<Option<usize>> {
+        self.row_loopers
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                self.note_assigner
+                    .row_is_active(i)
+                    .then(|| row.get_current_step())
+            })
+            .collect()
+    }
+
+    // how many pulses a note lasts, none if it is tied to the next note in the row
+    fn gate_pulses(&self, row: usize, params: &StepParams) -> Option<usize> {
+        let gate_length = match params.gate {
+            StepGate::Tie => return None,
+            StepGate::Length(length) => length,
+            StepGate::Row => self.row_gate_lengths[row].unwrap_or(self.gate_length),
+        };
+        let row_pulses = self.row_rates[row].pulses_per_step() as f32;
+        Some(((gate_length * row_pulses).round() as usize).max(1))
+    }
+
+    // work out when the notes triggered by a row should start and stop, applying the swing and
+    // groove for the step. A ratcheted step is split into evenly spaced hits, each with the gate
+    // scaled down to fit
+    fn schedule_notes(&mut self, row: usize, params: &StepParams, notes: &[note_assigner::Note]) {
+        let row_pulses = self.row_rates[row].pulses_per_step();
+        let step = self.pulse_counter / row_pulses;
+
+        let delay = (groove::swing_delay(self.swing, step) + self.groove.timing(step))
+            .min(groove::MAX_DELAY);
+        let step_on_pulse = self.pulse_counter + (delay * row_pulses as f32).round() as usize;
+        let gate_pulses = self.gate_pulses(row, params);
+
+        let accent = self.groove.accent(step);
+        let ratchet = params.ratchet.unwrap_or(Ratchet {
+            hits: 1,
+            ..Default::default()
+        });
+        // no more hits than there are pulses in the step, so no two land on the same pulse
+        let ratchet = Ratchet {
+            hits: ratchet.hits.clamp(1, Ratchet::MAX_HITS).min(row_pulses),
+            ..ratchet
+        };
+        let hits = ratchet.hits;
+
+        for hit in 0..hits {
+            let on_pulse = step_on_pulse + hit * row_pulses / hits;
+            let off_pulse = match gate_pulses {
+                Some(gate_pulses) => Some(on_pulse + (gate_pulses / hits).max(1)),
+                // the earlier hits of a tied ratchet last until the next hit
+                None if hit + 1 < hits => Some(step_on_pulse + (hit + 1) * row_pulses / hits),
+                None => None,
+            };
+            let (hit_velocity, transpose) = ratchet.hit(hit);
+
+            for note in notes {
+                let velocity = velocity::shape_velocity(
+                    note.velocity,
+                    self.fixed_velocity,
+                    self.velocity_curve,
+                    &self.row_velocities[row],
+                ) * accent
+                    * params.velocity.unwrap_or(1.0)
+                    * hit_velocity;
+                let mut note_number = note.note_number;
+                if transpose != 0 {
+                    note_number = (note_number as i32 + transpose).clamp(0, 127) as usize;
+                    note_number = self.note_assigner.quantize_for_row(row, note_number);
+                }
+                self.pending_notes.push(ScheduledNote {
+                    note: Note {
+                        note_number,
+                        velocity: velocity.round().clamp(1.0, 127.0) as usize,
+                    },
+                    row,
+                    on_pulse,
+                    off_pulse,
+                });
+            }
+        }
+    }
+
+    // take the notes that are due to start by this pulse out of pending.
+    // A tied note in the same row carries on into a new note of the same pitch, or overlaps a
+    // different one slightly so that it plays legato. Other playing notes of the same pitch are
+    // stopped so the new note can retrigger
+    fn notes_to_start(&mut self) -> Vec<ScheduledNote> {
+        let pulse = self.pulse_counter;
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.pending_notes.iter().partition(|n| n.on_pulse <= pulse);
+        self.pending_notes = pending;
+
+        let mut notes_to_start = vec![];
+        for new_note in due {
+            let mut carried_on = false;
+            for playing in self.playing_notes.iter_mut() {
+                let tied_in_row = playing.row == new_note.row && playing.off_pulse.is_none();
+                if playing.note.note_number == new_note.note.note_number {
+                    if tied_in_row {
+                        playing.off_pulse = new_note.off_pulse;
+                        carried_on = true;
+                    } else {
+                        playing.off_pulse = Some(pulse);
+                    }
+                } else if tied_in_row {
+                    playing.off_pulse = Some(pulse + 1);
+                }
+            }
+            if !carried_on {
+                notes_to_start.push(new_note);
             }
-            i += 1;
         }
-        steps
+        notes_to_start
     }
 
-    fn track_midi_notes(&mut self, notes: Vec<note_assigner::Note>) {
-        for note in notes {
-            self.playing_notes.push(note);
+    // a tie only lasts until the next step of its row, if that step doesn't play anything the
+    // tied notes stop on it
+    fn end_ties(&mut self, row: usize) {
+        let pulse = self.pulse_counter;
+        for playing in self.playing_notes.iter_mut() {
+            if playing.row == row && playing.off_pulse.is_none() {
+                playing.off_pulse = Some(pulse);
+            }
         }
     }
 
+    // remove the notes that are due to stop by this pulse
+    fn notes_to_stop(&mut self) -> Vec<(usize, Note)> {
+        let pulse = self.pulse_counter;
+        let is_due = |n: &ScheduledNote| n.off_pulse.map_or(false, |off| off <= pulse);
+        let notes_to_stop = self
+            .playing_notes
+            .iter()
+            .filter(|n| is_due(n))
+            .map(|n| (n.row, n.note))
+            .collect();
+        self.playing_notes.retain(|n| !is_due(n));
+        notes_to_stop
+    }
+
+    // tick the rows that are due to step on this pulse, return the ones that have an active step
     fn tick_rows(&mut self) -> Vec<usize> {
         let mut triggered_rows = vec![];
-        for i in 0..NUM_ROWS {
-            if let Some(t) = self.row_loopers[i].next() {
-                if t {
+        for i in 0..self.row_loopers.len() {
+            if self.pulse_counter % self.row_rates[i].pulses_per_step() != 0 {
+                continue;
+            }
+            if let Some(step) = self.row_loopers[i].next() {
+                if self.row_loopers[i].get_current_step() == 0 {
+                    self.row_loop_counts[i] += 1;
+                }
+                if step.active && self.step_plays(i, &step.params) {
                     triggered_rows.push(i);
+                } else {
+                    self.end_ties(i);
                 }
             }
         }
         triggered_rows
     }
+
+    // check the condition and probability of an active step
+    fn step_plays(&mut self, row: usize, params: &StepParams) -> bool {
+        let loop_count = self.row_loop_counts[row].max(1);
+        let condition_met = match params.condition {
+            TrigCondition::Always => true,
+            TrigCondition::Ratio(a, b) => {
+                let b = b.max(1) as usize;
+                loop_count % b == a as usize % b
+            }
+            TrigCondition::First => loop_count == 1,
+            TrigCondition::NotFirst => loop_count > 1,
+            TrigCondition::Fill => self.fill,
+            TrigCondition::NotFill => !self.fill,
+            TrigCondition::Previous => self.previous_condition_met[row],
+            TrigCondition::NotPrevious => !self.previous_condition_met[row],
+        };
+        let plays = condition_met
+            && params
+                .probability
+                .map_or(true, |probability| self.rng.gen::<f32>() < probability);
+
+        // the previous conditions look back at the other conditional steps, not at each other
+        let conditional = params.condition != TrigCondition::Always || params.probability.is_some();
+        let looks_back = matches!(
+            params.condition,
+            TrigCondition::Previous | TrigCondition::NotPrevious
+        );
+        if conditional && !looks_back {
+            self.previous_condition_met[row] = plays;
+        }
+        plays
+    }
 }
 
 // todo test this
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scale::ScaleKind;
 
     #[test]
     fn test_tick_rows() {
@@ -124,23 +563,526 @@ mod tests {
         rho.note_on(2, 100);
         rho.note_on(3, 100);
 
-        let two_true = looping_state::LoopingSequence::new(vec![true, true]);
-        let rows = [
-            two_true.clone(),
-            two_true.clone(),
-            two_true.clone(),
-            two_true.clone(),
-        ];
-
-        rho.row_loopers = rows;
+        let two_true = looping_state::LoopingSequence::new(vec![Step::new(true); 2]);
+        rho.row_loopers = vec![two_true; 4];
 
         let triggered_rows = rho.tick_rows();
         assert_eq!(triggered_rows, vec![0, 1, 2, 3]);
 
-        let notes = rho.on_clock_high();
+        let notes = rho.on_clock_pulse().note_ons;
         assert_eq!(notes.len(), 4);
 
         let playing_steps = rho.get_playing_steps();
-        assert_eq!(playing_steps, [Some(1), Some(1), Some(1), Some(1)]);
+        assert_eq!(playing_steps, vec![Some(1), Some(1), Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn test_row_rates() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(62, 100);
+
+        let all_on = looping_state::LoopingSequence::new(vec![Step::new(true); 4]);
+        rho.row_loopers = vec![all_on; 4];
+        rho.set_row_rate(0, RowRate::Double);
+        rho.set_row_rate(1, RowRate::Half);
+
+        // count the note ons from each row over two master steps
+        let mut row_0_count = 0;
+        let mut row_1_count = 0;
+        for _ in 0..PULSES_PER_STEP * 2 {
+            for (_, note) in rho.on_clock_pulse().note_ons {
+                if note.note_number == 60 {
+                    row_0_count += 1;
+                } else if note.note_number == 62 {
+                    row_1_count += 1;
+                }
+            }
+        }
+        assert_eq!(row_0_count, 4);
+        assert_eq!(row_1_count, 1);
+    }
+
+    #[test]
+    fn test_notes_stop_after_half_a_row_step() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.row_loopers[0] =
+            looping_state::LoopingSequence::new(vec![Step::new(true), Step::new(false)]);
+        rho.set_row_rate(0, RowRate::Triple);
+
+        assert_eq!(rho.on_clock_pulse().note_ons.len(), 1);
+        let pulses_to_off = RowRate::Triple.pulses_per_step() / 2;
+        for _ in 1..pulses_to_off {
+            assert!(rho.on_clock_pulse().note_offs.is_empty());
+        }
+        assert_eq!(rho.on_clock_pulse().note_offs.len(), 1);
+    }
+
+    #[test]
+    fn test_swing_delays_odd_steps() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(vec![Step::new(true); 4]);
+        rho.set_swing(75.0);
+
+        let mut note_on_pulses = vec![];
+        for pulse in 0..PULSES_PER_STEP * 4 {
+            if !rho.on_clock_pulse().note_ons.is_empty() {
+                note_on_pulses.push(pulse);
+            }
+        }
+        let half_step = PULSES_PER_STEP / 2;
+        assert_eq!(
+            note_on_pulses,
+            vec![
+                0,
+                PULSES_PER_STEP + half_step,
+                PULSES_PER_STEP * 2,
+                PULSES_PER_STEP * 3 + half_step
+            ]
+        );
+    }
+
+    #[test]
+    fn test_groove_accents() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(vec![Step::new(true); 2]);
+        rho.set_groove(Groove::new("test", vec![0.0], vec![1.0, 0.5]));
+
+        let mut velocities = vec![];
+        for _ in 0..PULSES_PER_STEP * 2 {
+            for (_, note) in rho.on_clock_pulse().note_ons {
+                velocities.push(note.velocity);
+            }
+        }
+        assert_eq!(velocities, vec![DEFAULT_VELOCITY, DEFAULT_VELOCITY / 2]);
+    }
+
+    #[test]
+    fn test_velocity_follows_held_note() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 40);
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(vec![Step::new(true); 1]);
+
+        let next_velocity = |rho: &mut Rho| loop {
+            if let Some((_, note)) = rho.on_clock_pulse().note_ons.first() {
+                return note.velocity;
+            }
+        };
+        assert_eq!(next_velocity(&mut rho), 40);
+
+        rho.set_row_velocity(
+            0,
+            RowVelocity {
+                scale: 2.0,
+                offset: 5,
+            },
+        );
+        assert_eq!(next_velocity(&mut rho), 85);
+
+        // a fixed velocity ignores the held note, and it all stays in the midi range
+        rho.set_fixed_velocity(Some(DEFAULT_VELOCITY));
+        assert_eq!(next_velocity(&mut rho), 127);
+        rho.set_row_velocity(
+            0,
+            RowVelocity {
+                scale: 0.0,
+                offset: 0,
+            },
+        );
+        assert_eq!(next_velocity(&mut rho), 1);
+    }
+
+    #[test]
+    fn test_step_velocity() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        let mut steps = vec![Step::new(true); 2];
+        steps[1].params.velocity = Some(0.5);
+        rho.set_row_activations(vec![steps, vec![], vec![], vec![]]);
+
+        let mut velocities = vec![];
+        for _ in 0..PULSES_PER_STEP * 2 {
+            for (_, note) in rho.on_clock_pulse().note_ons {
+                velocities.push(note.velocity);
+            }
+        }
+        assert_eq!(velocities, vec![100, 50]);
+    }
+
+    #[test]
+    fn test_ratchets() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.set_gate_length(0.5);
+        let params = StepParams {
+            ratchet: Some(Ratchet {
+                hits: 4,
+                velocity_ramp: -0.75,
+                pitch_ramp: 2,
+            }),
+            ..Default::default()
+        };
+        rho.set_row_activations(one_step_row(params));
+
+        let mut ons = vec![];
+        let mut offs = vec![];
+        for pulse in 0..PULSES_PER_STEP {
+            let pulse_notes = rho.on_clock_pulse();
+            for (_, note) in pulse_notes.note_ons {
+                ons.push((pulse, note.note_number, note.velocity));
+            }
+            offs.extend(pulse_notes.note_offs.iter().map(|_| pulse));
+        }
+        assert_eq!(
+            ons,
+            vec![(0, 60, 100), (6, 62, 75), (12, 64, 50), (18, 66, 25)]
+        );
+        // the gate is shared between the hits
+        assert_eq!(offs, vec![3, 9, 15, 21]);
+    }
+
+    #[test]
+    fn test_ratchet_faster_than_the_row_rate() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.set_row_rate(0, RowRate::Quadruple);
+        rho.set_row_activations(one_step_row(StepParams {
+            ratchet: Some(Ratchet {
+                hits: 8,
+                pitch_ramp: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+
+        let mut ons = vec![];
+        let mut offs = vec![];
+        for pulse in 0..RowRate::Quadruple.pulses_per_step() {
+            let pulse_notes = rho.on_clock_pulse();
+            for (_, note) in pulse_notes.note_ons {
+                ons.push((pulse, note.note_number));
+            }
+            for (_, note) in pulse_notes.note_offs {
+                offs.push((pulse, note.note_number));
+            }
+        }
+        // a hit on every pulse of the step, and each lasts until the next one
+        assert_eq!(
+            ons,
+            vec![(0, 60), (1, 61), (2, 62), (3, 63), (4, 64), (5, 65)]
+        );
+        assert_eq!(offs, vec![(1, 60), (2, 61), (3, 62), (4, 63), (5, 64)]);
+    }
+
+    #[test]
+    fn test_ratchet_pitch_ramp_stays_in_scale() {
+        let mut rho = Rho::new();
+        rho.set_scale(Scale {
+            kind: ScaleKind::MajorPentatonic,
+            ..Default::default()
+        });
+        rho.note_on(60, 100);
+        rho.set_row_activations(one_step_row(StepParams {
+            ratchet: Some(Ratchet {
+                hits: 4,
+                pitch_ramp: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+
+        let mut notes = vec![];
+        for _ in 0..PULSES_PER_STEP {
+            notes.extend(
+                rho.on_clock_pulse()
+                    .note_ons
+                    .iter()
+                    .map(|(_, n)| n.note_number),
+            );
+        }
+        assert_eq!(notes, vec![60, 62, 67, 69]);
+    }
+
+    #[test]
+    fn test_tied_ratchet() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        let params = StepParams {
+            gate: StepGate::Tie,
+            ratchet: Some(Ratchet {
+                hits: 3,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        rho.set_row_activations(one_step_row(params));
+
+        // each hit retriggers the note, the last one carries on into the next step
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP);
+        assert_eq!(ons, vec![0, 8, 16]);
+        assert_eq!(offs, vec![8, 16]);
+    }
+
+    #[test]
+    fn test_set_num_rows() {
+        let mut rho = Rho::new();
+        rho.set_row_rate(1, RowRate::Half);
+        rho.set_num_rows(6);
+        assert_eq!(rho.num_rows(), 6);
+        assert_eq!(rho.get_playing_steps().len(), 6);
+        assert_eq!(rho.get_notes_for_rows().len(), 6);
+        assert_eq!(rho.row_rates[1], RowRate::Half);
+
+        // notes are spread over the new rows too
+        for note in 60..66 {
+            rho.note_on(note, 100);
+        }
+        assert_eq!(rho.get_notes_for_rows()[5][0].note_number, 65);
+
+        rho.set_num_rows(0);
+        assert_eq!(rho.num_rows(), 1);
+        rho.set_num_rows(100);
+        assert_eq!(rho.num_rows(), MAX_ROWS);
+    }
+
+    // whether row 0 plays on each of a number of steps
+    fn row_plays(rho: &mut Rho, steps: usize) -> Vec<bool> {
+        let mut plays = vec![];
+        for _ in 0..steps {
+            plays.push(rho.tick_rows().contains(&0));
+            rho.pulse_counter += PULSES_PER_STEP;
+        }
+        plays
+    }
+
+    fn one_step_row(params: StepParams) -> Vec<Vec<Step>> {
+        vec![
+            vec![Step {
+                active: true,
+                params,
+            }],
+            vec![],
+            vec![],
+            vec![],
+        ]
+    }
+
+    #[test]
+    fn test_step_probability() {
+        let mut rho = Rho::new();
+        rho.set_random_seed(1);
+        let mut params = StepParams {
+            probability: Some(0.5),
+            ..Default::default()
+        };
+        rho.set_row_activations(one_step_row(params));
+        let plays = row_plays(&mut rho, 1000);
+        let count = plays.iter().filter(|&&p| p).count();
+        assert!(count > 400 && count < 600);
+
+        // the same seed gives the same steps
+        rho.reset();
+        rho.set_random_seed(1);
+        assert_eq!(row_plays(&mut rho, 1000), plays);
+
+        params.probability = Some(0.0);
+        rho.set_row_activations(one_step_row(params));
+        assert_eq!(row_plays(&mut rho, 10), vec![false; 10]);
+    }
+
+    #[test]
+    fn test_trig_conditions() {
+        let expected = [
+            (
+                TrigCondition::Ratio(1, 2),
+                [true, false, true, false, true, false],
+            ),
+            (
+                TrigCondition::Ratio(2, 4),
+                [false, true, false, false, false, true],
+            ),
+            (
+                TrigCondition::First,
+                [true, false, false, false, false, false],
+            ),
+            (
+                TrigCondition::NotFirst,
+                [false, true, true, true, true, true],
+            ),
+            (TrigCondition::Fill, [false; 6]),
+            (TrigCondition::NotFill, [true; 6]),
+        ];
+        for (condition, expected) in expected {
+            let mut rho = Rho::new();
+            rho.set_row_activations(one_step_row(StepParams {
+                condition,
+                ..Default::default()
+            }));
+            assert_eq!(row_plays(&mut rho, 6), expected, "{:?}", condition);
+        }
+
+        // the loop count goes by the length of the row, and starts again on reset
+        let mut rho = Rho::new();
+        let mut steps = vec![Step::new(true); 2];
+        steps[0].params.condition = TrigCondition::Ratio(1, 2);
+        rho.set_row_activations(vec![steps, vec![], vec![], vec![]]);
+        assert_eq!(
+            row_plays(&mut rho, 6),
+            vec![true, true, false, true, true, true]
+        );
+        rho.reset();
+        assert_eq!(row_plays(&mut rho, 2), vec![true, true]);
+
+        rho.set_fill(true);
+        rho.set_row_activations(one_step_row(StepParams {
+            condition: TrigCondition::Fill,
+            ..Default::default()
+        }));
+        assert_eq!(row_plays(&mut rho, 2), vec![true, true]);
+    }
+
+    #[test]
+    fn test_previous_condition() {
+        let mut rho = Rho::new();
+        let mut steps = vec![Step::new(true); 2];
+        steps[0].params.condition = TrigCondition::Ratio(1, 2);
+        steps[1].params.condition = TrigCondition::Previous;
+        rho.set_row_activations(vec![steps, vec![], vec![], vec![]]);
+        assert_eq!(
+            row_plays(&mut rho, 6),
+            vec![true, true, false, false, true, true]
+        );
+
+        let mut steps = vec![Step::new(true); 2];
+        steps[0].params.condition = TrigCondition::Ratio(1, 2);
+        steps[1].params.condition = TrigCondition::NotPrevious;
+        rho.reset();
+        rho.set_row_activations(vec![steps, vec![], vec![], vec![]]);
+        assert_eq!(
+            row_plays(&mut rho, 6),
+            vec![true, false, false, true, true, false]
+        );
+    }
+
+    // returns the pulses that the note ons and offs happen on over a number of pulses
+    fn note_pulses(rho: &mut Rho, num_pulses: usize) -> (Vec<usize>, Vec<usize>) {
+        let mut ons = vec![];
+        let mut offs = vec![];
+        for pulse in 0..num_pulses {
+            let pulse_notes = rho.on_clock_pulse();
+            ons.extend(pulse_notes.note_ons.iter().map(|_| pulse));
+            offs.extend(pulse_notes.note_offs.iter().map(|_| pulse));
+        }
+        (ons, offs)
+    }
+
+    #[test]
+    fn test_gate_lengths() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        let mut steps = vec![
+            Step::new(true),
+            Step::new(true),
+            Step::new(true),
+            Step::new(false),
+        ];
+        steps[2].params.gate = StepGate::Length(1.5);
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(steps);
+
+        rho.set_gate_length(0.25);
+        let (_, offs) = note_pulses(&mut rho, PULSES_PER_STEP);
+        assert_eq!(offs, vec![PULSES_PER_STEP / 4]);
+
+        // the row gate overrides the global gate
+        rho.set_row_gate_length(0, Some(0.75));
+        let (_, offs) = note_pulses(&mut rho, PULSES_PER_STEP);
+        assert_eq!(offs, vec![PULSES_PER_STEP * 3 / 4]);
+
+        // the step gate overrides both, and carries on into the next step
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP * 2);
+        assert_eq!(ons, vec![0]);
+        assert_eq!(offs, vec![PULSES_PER_STEP * 3 / 2]);
+    }
+
+    #[test]
+    fn test_tie_to_same_note_does_not_retrigger() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        let mut steps = vec![Step::new(true), Step::new(true), Step::new(false)];
+        steps[0].params.gate = StepGate::Tie;
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(steps);
+
+        // the first note carries on into the second step
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP * 2);
+        assert_eq!(ons, vec![0]);
+        assert_eq!(offs, vec![PULSES_PER_STEP + PULSES_PER_STEP / 2]);
+    }
+
+    #[test]
+    fn test_tie_ends_at_a_silent_step() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        let mut steps = vec![Step::new(true), Step::new(false), Step::new(true)];
+        steps[0].params.gate = StepGate::Tie;
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(steps);
+
+        // the tie stops at the empty step rather than holding until the next note
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP * 3);
+        assert_eq!(ons, vec![0, PULSES_PER_STEP * 2]);
+        assert_eq!(
+            offs,
+            vec![PULSES_PER_STEP, PULSES_PER_STEP * 2 + PULSES_PER_STEP / 2]
+        );
+
+        // a tie on the only step of a row stops when the notes are let go
+        let mut steps = vec![Step::new(true)];
+        steps[0].params.gate = StepGate::Tie;
+        rho.stop_all_notes();
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(steps);
+        let (ons, _) = note_pulses(&mut rho, PULSES_PER_STEP * 2);
+        assert_eq!(ons, vec![0]);
+        rho.note_off(60);
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP);
+        assert_eq!((ons, offs), (vec![], vec![0]));
+    }
+
+    #[test]
+    fn test_tie_to_different_note_overlaps() {
+        let mut rho = Rho::new();
+        rho.note_on(60, 100);
+        rho.note_on(64, 100);
+        // both notes on one row
+        rho.note_assigner.set_row_active(1, false);
+        rho.note_assigner.set_row_active(2, false);
+        rho.note_assigner.set_row_active(3, false);
+        rho.note_off(64);
+        rho.note_on(64, 100);
+
+        let mut steps = vec![Step::new(true); 2];
+        steps[0].params.gate = StepGate::Tie;
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(steps);
+
+        let (ons, offs) = note_pulses(&mut rho, PULSES_PER_STEP * 2);
+        assert_eq!(ons, vec![0, PULSES_PER_STEP]);
+        // the tied note stops just after the next one starts
+        assert_eq!(
+            offs,
+            vec![PULSES_PER_STEP + 1, PULSES_PER_STEP + PULSES_PER_STEP / 2]
+        );
+    }
+
+    #[test]
+    fn test_set_pulse_position() {
+        let mut rho = Rho::new();
+        rho.row_loopers[0] = looping_state::LoopingSequence::new(vec![Step::new(true); 8]);
+        rho.set_row_rate(0, RowRate::Double);
+
+        // half way through the second master step, which is the fourth double speed step
+        rho.set_pulse_position(PULSES_PER_STEP + PULSES_PER_STEP / 2);
+        rho.on_clock_pulse();
+        assert_eq!(rho.get_playing_steps()[0], Some(3));
     }
 }
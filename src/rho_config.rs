@@ -1 +1,11 @@
-pub const NUM_ROWS: usize = 4;
+// the number of rows can be changed while running, up to the max
+pub const DEFAULT_NUM_ROWS: usize = 4;
+pub const MAX_ROWS: usize = 16;
+pub const MAX_ROW_LENGTH: usize = 64;
+
+// a step is a quarter note at the displayed tempo, so the midi clock runs at 24 pulses per step
+pub const PULSES_PER_STEP: usize = 24;
+
+// the range of the tempo slider, tapped tempos are kept inside it too
+pub const MIN_TEMPO: f32 = 40.0;
+pub const MAX_TEMPO: f32 = 1000.0;
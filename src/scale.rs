@@ -0,0 +1,176 @@
+// keeps the notes that are played in a key, by moving them to the nearest note of a scale
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleKind {
+    #[default]
+    Chromatic, // every note, so nothing is changed
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    HarmonicMinor,
+    Custom, // uses the custom mask of the scale
+}
+
+impl ScaleKind {
+    pub const ALL: [ScaleKind; 12] = [
+        ScaleKind::Chromatic,
+        ScaleKind::Major,
+        ScaleKind::Minor,
+        ScaleKind::Dorian,
+        ScaleKind::Phrygian,
+        ScaleKind::Lydian,
+        ScaleKind::Mixolydian,
+        ScaleKind::Locrian,
+        ScaleKind::MajorPentatonic,
+        ScaleKind::MinorPentatonic,
+        ScaleKind::HarmonicMinor,
+        ScaleKind::Custom,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleKind::Chromatic => "Chromatic",
+            ScaleKind::Major => "Major",
+            ScaleKind::Minor => "Minor",
+            ScaleKind::Dorian => "Dorian",
+            ScaleKind::Phrygian => "Phrygian",
+            ScaleKind::Lydian => "Lydian",
+            ScaleKind::Mixolydian => "Mixolydian",
+            ScaleKind::Locrian => "Locrian",
+            ScaleKind::MajorPentatonic => "Major Pentatonic",
+            ScaleKind::MinorPentatonic => "Minor Pentatonic",
+            ScaleKind::HarmonicMinor => "Harmonic Minor",
+            ScaleKind::Custom => "Custom",
+        }
+    }
+
+    // the semitones above the root that are in the scale, none for a custom scale
+    fn intervals(&self) -> Option<&'static [usize]> {
+        match self {
+            ScaleKind::Chromatic => Some(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+            ScaleKind::Major => Some(&[0, 2, 4, 5, 7, 9, 11]),
+            ScaleKind::Minor => Some(&[0, 2, 3, 5, 7, 8, 10]),
+            ScaleKind::Dorian => Some(&[0, 2, 3, 5, 7, 9, 10]),
+            ScaleKind::Phrygian => Some(&[0, 1, 3, 5, 7, 8, 10]),
+            ScaleKind::Lydian => Some(&[0, 2, 4, 6, 7, 9, 11]),
+            ScaleKind::Mixolydian => Some(&[0, 2, 4, 5, 7, 9, 10]),
+            ScaleKind::Locrian => Some(&[0, 1, 3, 5, 6, 8, 10]),
+            ScaleKind::MajorPentatonic => Some(&[0, 2, 4, 7, 9]),
+            ScaleKind::MinorPentatonic => Some(&[0, 3, 5, 7, 10]),
+            ScaleKind::HarmonicMinor => Some(&[0, 2, 3, 5, 7, 8, 11]),
+            ScaleKind::Custom => None,
+        }
+    }
+}
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    pub root: usize, // 0 is C
+    pub kind: ScaleKind,
+    // bit n is set if the note n semitones above the root is in the scale
+    pub custom_mask: u16,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale {
+            root: 0,
+            kind: ScaleKind::Chromatic,
+            custom_mask: 0xFFF,
+        }
+    }
+}
+
+impl Scale {
+    pub fn mask(&self) -> u16 {
+        match self.kind.intervals() {
+            Some(intervals) => intervals.iter().fold(0, |mask, i| mask | 1 << i),
+            None => self.custom_mask & 0xFFF,
+        }
+    }
+
+    pub fn contains(&self, note_number: usize) -> bool {
+        let degree = (note_number + 12 - self.root % 12) % 12;
+        self.mask() & (1 << degree) != 0
+    }
+
+    // the nearest note in the scale, going down if two are as near.
+    // With nothing in the scale the note is left alone
+    pub fn quantize(&self, note_number: usize) -> usize {
+        if self.mask() == 0 {
+            return note_number;
+        }
+        for distance in 0..12 {
+            if note_number >= distance && self.contains(note_number - distance) {
+                return note_number - distance;
+            }
+            if note_number + distance <= 127 && self.contains(note_number + distance) {
+                return note_number + distance;
+            }
+        }
+        note_number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks() {
+        let major = Scale {
+            kind: ScaleKind::Major,
+            ..Default::default()
+        };
+        assert_eq!(major.mask(), 0b1010_1011_0101);
+        assert_eq!(Scale::default().mask(), 0xFFF);
+
+        let custom = Scale {
+            kind: ScaleKind::Custom,
+            custom_mask: 0b1001_0001,
+            ..Default::default()
+        };
+        assert!(custom.contains(48) && custom.contains(52) && custom.contains(55));
+        assert!(!custom.contains(50));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let d_minor = Scale {
+            root: 2,
+            kind: ScaleKind::Minor,
+            ..Default::default()
+        };
+        // D E F G A Bb C
+        assert_eq!(d_minor.quantize(62), 62);
+        assert_eq!(d_minor.quantize(66), 65); // F# goes down to F
+        assert_eq!(d_minor.quantize(71), 70); // B to Bb
+        assert_eq!(d_minor.quantize(61), 60); // C# is between C and D, so goes down
+
+        let pentatonic = Scale {
+            kind: ScaleKind::MajorPentatonic,
+            ..Default::default()
+        };
+        assert_eq!(pentatonic.quantize(65), 64);
+        assert_eq!(pentatonic.quantize(71), 72);
+        assert_eq!(pentatonic.quantize(127), 127);
+
+        // an empty scale changes nothing
+        let empty = Scale {
+            kind: ScaleKind::Custom,
+            custom_mask: 0,
+            ..Default::default()
+        };
+        assert_eq!(empty.quantize(61), 61);
+    }
+}
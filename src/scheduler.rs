@@ -0,0 +1,194 @@
+// works out the time of each clock pulse from the tempo, and measures how late they happen
+
+use crate::rho_config::PULSES_PER_STEP;
+use std::time::{Duration, Instant};
+
+// keeps track of how late the pulses were compared to when they were scheduled
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JitterStats {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl JitterStats {
+    pub fn record(&mut self, lateness: Duration) {
+        self.count += 1;
+        self.total += lateness;
+        self.max = self.max.max(lateness);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+pub struct Scheduler {
+    tempo: f32,
+    // how much faster or slower to play than the tempo e.g. 0.05 is 5% faster
+    nudge: f32,
+    pulse_duration: Duration,
+    // the scheduled time of a pulse, the following pulses are counted from it so that rounding
+    // and late pulses don't add up
+    anchor: Instant,
+    pulses_since_anchor: u32,
+    jitter: JitterStats,
+}
+
+impl Scheduler {
+    pub fn new(now: Instant) -> Self {
+        let mut scheduler = Scheduler {
+            tempo: 120.0,
+            nudge: 0.0,
+            pulse_duration: Duration::ZERO,
+            anchor: now,
+            pulses_since_anchor: 0,
+            jitter: JitterStats::default(),
+        };
+        scheduler.update_pulse_duration();
+        scheduler
+    }
+
+    // the first pulse happens straight away
+    pub fn start(&mut self, now: Instant) {
+        self.anchor = now;
+        self.pulses_since_anchor = 0;
+    }
+
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.tempo = tempo;
+        self.update_pulse_duration();
+    }
+
+    // temporarily play faster or slower to line up with someone else, without changing the tempo
+    pub fn set_nudge(&mut self, nudge: f32) {
+        self.nudge = nudge;
+        self.update_pulse_duration();
+    }
+
+    // the next pulse keeps its time, the ones after it are spaced by the new rate
+    fn update_pulse_duration(&mut self) {
+        self.anchor = self.next_pulse_time();
+        self.pulses_since_anchor = 0;
+        let pulses_per_minute =
+            self.tempo as f64 * (1.0 + self.nudge as f64) * PULSES_PER_STEP as f64;
+        self.pulse_duration = Duration::from_nanos((60e9 / pulses_per_minute) as u64);
+    }
+
+    pub fn pulse_duration(&self) -> Duration {
+        self.pulse_duration
+    }
+
+    pub fn next_pulse_time(&self) -> Instant {
+        self.anchor + self.pulse_duration * self.pulses_since_anchor
+    }
+
+    // call when the pulse has been sent, with the time it happened
+    pub fn on_pulse(&mut self, now: Instant) {
+        self.jitter
+            .record(now.saturating_duration_since(self.next_pulse_time()));
+        self.pulses_since_anchor += 1;
+
+        // if we've fallen a whole pulse behind, start counting again rather than rushing to
+        // catch up
+        if now > self.next_pulse_time() {
+            self.anchor = now;
+            self.pulses_since_anchor = 1;
+        }
+    }
+
+    pub fn jitter(&self) -> JitterStats {
+        self.jitter
+    }
+
+    pub fn reset_jitter(&mut self) {
+        self.jitter = JitterStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_times() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(start);
+        scheduler.set_tempo(60.0);
+        scheduler.start(start);
+
+        let pulse = Duration::from_secs(1) / PULSES_PER_STEP as u32;
+        assert_eq!(scheduler.pulse_duration(), pulse);
+        assert_eq!(scheduler.next_pulse_time(), start);
+
+        scheduler.on_pulse(start);
+        assert_eq!(scheduler.next_pulse_time(), start + pulse);
+
+        // a late pulse doesn't push the following ones back
+        scheduler.on_pulse(start + pulse + Duration::from_micros(300));
+        assert_eq!(scheduler.next_pulse_time(), start + pulse * 2);
+
+        let jitter = scheduler.jitter();
+        assert_eq!(jitter.count(), 2);
+        assert_eq!(jitter.max(), Duration::from_micros(300));
+        assert_eq!(jitter.mean(), Duration::from_micros(150));
+
+        scheduler.reset_jitter();
+        assert_eq!(scheduler.jitter().count(), 0);
+    }
+
+    #[test]
+    fn test_tempo_change_keeps_next_pulse() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(start);
+        scheduler.set_tempo(60.0);
+        scheduler.start(start);
+        scheduler.on_pulse(start);
+
+        let pulse = Duration::from_secs(1) / PULSES_PER_STEP as u32;
+        scheduler.set_tempo(120.0);
+        assert_eq!(scheduler.next_pulse_time(), start + pulse);
+        scheduler.on_pulse(start + pulse);
+        assert_eq!(scheduler.next_pulse_time(), start + pulse + pulse / 2);
+    }
+
+    #[test]
+    fn test_nudge() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(start);
+        scheduler.set_tempo(60.0);
+        scheduler.start(start);
+
+        let pulse = Duration::from_secs(1) / PULSES_PER_STEP as u32;
+        scheduler.set_nudge(1.0);
+        assert_eq!(scheduler.pulse_duration(), pulse / 2);
+
+        // letting go of the nudge goes back to the tempo
+        scheduler.set_nudge(0.0);
+        assert_eq!(scheduler.pulse_duration(), pulse);
+    }
+
+    #[test]
+    fn test_very_late_pulse_restarts_counting() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(start);
+        scheduler.set_tempo(60.0);
+        scheduler.start(start);
+
+        let pulse = Duration::from_secs(1) / PULSES_PER_STEP as u32;
+        let late = start + pulse * 3;
+        scheduler.on_pulse(late);
+        assert_eq!(scheduler.next_pulse_time(), late + pulse);
+    }
+}
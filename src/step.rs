@@ -0,0 +1,115 @@
+// the data for a single step of a row, as sent from the gui to rho
+
+// how long the note played by a step lasts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StepGate {
+    #[default]
+    Row, // use the gate length of the row
+    Length(f32), // as a fraction of the step, can be more than 1 to overlap the next step
+    Tie,         // hold the note until the next note in the row starts
+}
+
+// conditions for an active step to play, checked each time the row gets to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrigCondition {
+    #[default]
+    Always,
+    // plays on the first number loop out of every second number e.g. 2:4 plays on loops 2, 6, 10...
+    Ratio(u8, u8),
+    First,    // only the first time round after starting
+    NotFirst, // every time apart from the first
+    Fill,     // only while fill is held
+    NotFill,
+    Previous, // if the last step with a condition in the row played
+    NotPrevious,
+}
+
+impl TrigCondition {
+    pub const ALL: [TrigCondition; 16] = [
+        TrigCondition::Always,
+        TrigCondition::Ratio(1, 2),
+        TrigCondition::Ratio(2, 2),
+        TrigCondition::Ratio(1, 3),
+        TrigCondition::Ratio(2, 3),
+        TrigCondition::Ratio(3, 3),
+        TrigCondition::Ratio(1, 4),
+        TrigCondition::Ratio(2, 4),
+        TrigCondition::Ratio(3, 4),
+        TrigCondition::Ratio(4, 4),
+        TrigCondition::First,
+        TrigCondition::NotFirst,
+        TrigCondition::Fill,
+        TrigCondition::NotFill,
+        TrigCondition::Previous,
+        TrigCondition::NotPrevious,
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            TrigCondition::Always => "Always".to_string(),
+            TrigCondition::Ratio(a, b) => format!("{}:{}", a, b),
+            TrigCondition::First => "First".to_string(),
+            TrigCondition::NotFirst => "Not First".to_string(),
+            TrigCondition::Fill => "Fill".to_string(),
+            TrigCondition::NotFill => "Not Fill".to_string(),
+            TrigCondition::Previous => "Previous".to_string(),
+            TrigCondition::NotPrevious => "Not Previous".to_string(),
+        }
+    }
+}
+
+// repeats a step several times within its length, for rolls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ratchet {
+    pub hits: usize,        // from 2 to 8
+    pub velocity_ramp: f32, // added to the velocity scale by the last hit, e.g. -0.5 fades out
+    pub pitch_ramp: i32,    // semitones added on each hit after the first
+}
+
+impl Ratchet {
+    pub const MAX_HITS: usize = 8;
+
+    // the velocity scale and transpose for one of the hits
+    pub fn hit(&self, index: usize) -> (f32, i32) {
+        let ramp_position = index as f32 / (self.hits.max(2) - 1) as f32;
+        (
+            (1.0 + self.velocity_ramp * ramp_position).max(0.0),
+            self.pitch_ramp * index as i32,
+        )
+    }
+}
+
+impl Default for Ratchet {
+    fn default() -> Self {
+        Ratchet {
+            hits: 2,
+            velocity_ramp: 0.0,
+            pitch_ramp: 0,
+        }
+    }
+}
+
+// settings for a step on top of whether it is active
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StepParams {
+    pub gate: StepGate,
+    pub velocity: Option<f32>, // scales the velocity of the note, from 0 to 1. None is full velocity
+    pub probability: Option<f32>, // chance of playing from 0 to 1. None always plays
+    pub condition: TrigCondition,
+    pub ratchet: Option<Ratchet>, // None plays the step once
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Step {
+    pub active: bool,
+    pub params: StepParams,
+}
+
+impl Step {
+    pub fn new(active: bool) -> Self {
+        Step {
+            active,
+            params: StepParams::default(),
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use eframe::egui;
 use egui::Color32;
 
-pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egui::Response {
+pub fn step_switch_ui(
+    ui: &mut egui::Ui,
+    on: &mut bool,
+    velocity: &mut Option<f32>,
+    is_playing: bool,
+) -> egui::Response {
     let desired_height = ui.spacing().interact_size.y * 2.0;
     // use all available width
     let desired_width = ui.available_width();
@@ -10,13 +15,22 @@ pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egu
     // 2. Allocating space:
     // This is where we get a region of the screen assigned.
     // We also tell the Ui to sense clicks in the allocated region.
-    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click());
+    let (rect, mut response) = ui.allocate_exact_size(desired_size, egui::Sense::click_and_drag());
 
     if response.clicked() {
         *on = !*on;
         response.mark_changed(); // report back that the value changed
     }
 
+    // dragging up and down sets the velocity, the top of the switch is full velocity
+    if response.dragged() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let level = (rect.bottom() - pos.y) / rect.height();
+            *velocity = Some(level.clamp(0.0, 1.0));
+            response.mark_changed();
+        }
+    }
+
     // Attach some meta-data to the response which can be used by screen readers:
     response.widget_info(|| egui::WidgetInfo::selected(egui::WidgetType::Checkbox, *on, ""));
 
@@ -43,15 +57,27 @@ pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egu
             egui::lerp((off_color.b() as f32)..=(on_color.b() as f32), how_on) as u8,
         );
         // if playing, paint a border around the switch
-        if is_playing {
+        let inner_rect = if is_playing {
             ui.painter()
                 .rect(rect, radius, playing_color, visuals.bg_stroke);
-            let inner_rect = rect.expand(-radius / 2.0); // make room for the border
-            ui.painter()
-                .rect(inner_rect, radius, fill_color, visuals.bg_stroke);
+            rect.expand(-radius / 2.0) // make room for the border
         } else {
-            ui.painter()
-                .rect(rect, radius, fill_color, visuals.bg_stroke);
+            rect
+        };
+
+        match velocity {
+            // the velocity is shown by how much of the switch is filled, from the bottom
+            Some(level) if *on => {
+                ui.painter()
+                    .rect(inner_rect, radius, off_color, visuals.bg_stroke);
+                let mut level_rect = inner_rect;
+                level_rect.set_top(inner_rect.bottom() - inner_rect.height() * *level);
+                ui.painter().rect_filled(level_rect, radius, fill_color);
+            }
+            _ => {
+                ui.painter()
+                    .rect(inner_rect, radius, fill_color, visuals.bg_stroke);
+            }
         }
     }
 
@@ -67,8 +93,12 @@ pub fn step_switch_ui(ui: &mut egui::Ui, on: &mut bool, is_playing: bool) -> egu
 /// ``` ignore
 /// ui.add(toggle(&mut my_bool));
 /// ```
-pub fn step_switch(on: &mut bool, is_playing: bool) -> impl egui::Widget + '_ {
-    move |ui: &mut egui::Ui| step_switch_ui(ui, on, is_playing)
+pub fn step_switch<'a>(
+    on: &'a mut bool,
+    velocity: &'a mut Option<f32>,
+    is_playing: bool,
+) -> impl egui::Widget + 'a {
+    move |ui: &mut egui::Ui| step_switch_ui(ui, on, velocity, is_playing)
 }
 
 pub fn url_to_file_source_code() -> String {
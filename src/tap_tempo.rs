@@ -0,0 +1,96 @@
+// works out a tempo from the average time between recent taps
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// only the most recent taps are averaged, so the tempo can follow changes
+const MAX_TAPS: usize = 8;
+// a gap longer than this starts a new set of taps
+const MAX_TAP_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+pub struct TapTempo {
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    pub fn new() -> Self {
+        TapTempo {
+            taps: VecDeque::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.taps.clear();
+    }
+
+    // call for every tap, returns the bpm once there are at least two taps
+    pub fn on_tap(&mut self, now: Instant) -> Option<f32> {
+        if let Some(last) = self.taps.back() {
+            if now.saturating_duration_since(*last) > MAX_TAP_INTERVAL {
+                self.taps.clear();
+            }
+        }
+        self.taps.push_back(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.pop_front();
+        }
+
+        // the average interval is the time from the first to the last tap over the number of gaps
+        let first = *self.taps.front()?;
+        let gaps = self.taps.len() as u32 - 1;
+        let interval = now.saturating_duration_since(first).checked_div(gaps)?;
+        if interval.is_zero() {
+            return None;
+        }
+        Some(60.0 / interval.as_secs_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tap_tempo() {
+        let start = Instant::now();
+        let mut tap_tempo = TapTempo::new();
+        assert_eq!(tap_tempo.on_tap(start), None);
+        assert_eq!(
+            tap_tempo.on_tap(start + Duration::from_millis(500)),
+            Some(120.0)
+        );
+
+        // uneven taps are averaged
+        let bpm = tap_tempo
+            .on_tap(start + Duration::from_millis(1200))
+            .unwrap();
+        assert!((bpm - 100.0).abs() < 0.01);
+
+        // a long gap starts again
+        let restart = start + Duration::from_secs(5);
+        assert_eq!(tap_tempo.on_tap(restart), None);
+        assert_eq!(
+            tap_tempo.on_tap(restart + Duration::from_secs(1)),
+            Some(60.0)
+        );
+    }
+
+    #[test]
+    fn test_only_recent_taps_count() {
+        let start = Instant::now();
+        let mut tap_tempo = TapTempo::new();
+        let mut time = start;
+        for _ in 0..MAX_TAPS {
+            tap_tempo.on_tap(time);
+            time += Duration::from_millis(1000);
+        }
+        // after enough faster taps the slow ones are forgotten
+        let mut bpm = None;
+        for _ in 0..MAX_TAPS {
+            bpm = tap_tempo.on_tap(time);
+            time += Duration::from_millis(500);
+        }
+        assert_eq!(bpm, Some(120.0));
+    }
+}
@@ -0,0 +1,106 @@
+// estimates the tempo of incoming midi clock, smoothing out the jitter between pulses
+
+use crate::rho_config::PULSES_PER_STEP;
+
+// how much each new pulse interval moves the estimate, smaller is smoother but slower to follow
+const SMOOTHING: f64 = 0.1;
+// if the gap between pulses jumps by more than this factor, start estimating again
+const MAX_INTERVAL_JUMP: f64 = 4.0;
+
+pub struct TempoEstimator {
+    last_stamp: Option<u64>,
+    smoothed_interval: Option<f64>, // microseconds between pulses
+}
+
+impl Default for TempoEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TempoEstimator {
+    pub fn new() -> Self {
+        TempoEstimator {
+            last_stamp: None,
+            smoothed_interval: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.last_stamp = None;
+        self.smoothed_interval = None;
+    }
+
+    // call for every midi clock pulse with its timestamp in microseconds, returns the bpm estimate
+    // once there is one
+    pub fn on_pulse(&mut self, stamp: u64) -> Option<f32> {
+        let last_stamp = self.last_stamp.replace(stamp);
+        let interval = stamp.checked_sub(last_stamp?)? as f64;
+        if interval <= 0.0 {
+            return self.bpm();
+        }
+
+        self.smoothed_interval = match self.smoothed_interval {
+            Some(smoothed)
+                if interval < smoothed * MAX_INTERVAL_JUMP
+                    && interval > smoothed / MAX_INTERVAL_JUMP =>
+            {
+                Some(smoothed + SMOOTHING * (interval - smoothed))
+            }
+            _ => Some(interval),
+        };
+        self.bpm()
+    }
+
+    pub fn bpm(&self) -> Option<f32> {
+        self.smoothed_interval
+            .map(|interval| (60_000_000.0 / (interval * PULSES_PER_STEP as f64)) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_clock() {
+        let mut estimator = TempoEstimator::new();
+        // 120 bpm is 2 steps per second, 48 pulses per second
+        let interval = 1_000_000 / 48;
+        assert_eq!(estimator.on_pulse(0), None);
+        let mut bpm = None;
+        for i in 1..48 {
+            bpm = estimator.on_pulse(i * interval);
+        }
+        assert!((bpm.unwrap() - 120.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_jitter_is_smoothed() {
+        let mut estimator = TempoEstimator::new();
+        let interval = 1_000_000 / 48;
+        let mut stamp = 0;
+        for i in 0..200 {
+            // alternate early and late pulses
+            let jitter = if i % 2 == 0 { 2000 } else { 0 };
+            estimator.on_pulse(stamp + jitter);
+            stamp += interval;
+        }
+        let bpm = estimator.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn test_tempo_jump_resets_estimate() {
+        let mut estimator = TempoEstimator::new();
+        estimator.on_pulse(0);
+        estimator.on_pulse(20_000);
+        // a long gap, e.g. after the clock was paused
+        let bpm = estimator.on_pulse(1_000_000).unwrap();
+        let expected = 60_000_000.0 / (980_000.0 * PULSES_PER_STEP as f32);
+        assert!((bpm - expected).abs() < 0.01);
+
+        estimator.reset();
+        assert_eq!(estimator.bpm(), None);
+    }
+}
@@ -0,0 +1,104 @@
+// shaping the velocity of the held notes before they are sent out
+
+// how the input velocity maps to the output velocity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Exponential, // soft notes get softer
+    Logarithmic, // soft notes get louder
+    Compressed,  // everything moves towards the middle
+}
+
+impl VelocityCurve {
+    pub const ALL: [VelocityCurve; 4] = [
+        VelocityCurve::Linear,
+        VelocityCurve::Exponential,
+        VelocityCurve::Logarithmic,
+        VelocityCurve::Compressed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VelocityCurve::Linear => "Linear",
+            VelocityCurve::Exponential => "Exp",
+            VelocityCurve::Logarithmic => "Log",
+            VelocityCurve::Compressed => "Compressed",
+        }
+    }
+
+    // takes and returns a velocity from 0 to 1
+    pub fn apply(&self, velocity: f32) -> f32 {
+        let velocity = velocity.clamp(0.0, 1.0);
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential => velocity * velocity,
+            VelocityCurve::Logarithmic => velocity.sqrt(),
+            VelocityCurve::Compressed => 0.5 + velocity * 0.5,
+        }
+    }
+}
+
+// adjusts the velocity of everything a row plays, after the curve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowVelocity {
+    pub scale: f32,
+    pub offset: i32,
+}
+
+impl Default for RowVelocity {
+    fn default() -> Self {
+        RowVelocity {
+            scale: 1.0,
+            offset: 0,
+        }
+    }
+}
+
+// the whole chain from input velocity to output velocity, before any accents
+// a fixed velocity replaces the input velocity but still goes through the curve and row settings
+pub fn shape_velocity(
+    input: usize,
+    fixed: Option<usize>,
+    curve: VelocityCurve,
+    row: &RowVelocity,
+) -> f32 {
+    let velocity = fixed.unwrap_or(input) as f32 / 127.0;
+    curve.apply(velocity) * 127.0 * row.scale + row.offset as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curves() {
+        for curve in VelocityCurve::ALL {
+            // the curves all keep the top end where it is
+            assert_eq!(curve.apply(1.0), 1.0, "{:?}", curve);
+        }
+        assert_eq!(VelocityCurve::Linear.apply(0.5), 0.5);
+        assert_eq!(VelocityCurve::Exponential.apply(0.5), 0.25);
+        assert_eq!(VelocityCurve::Logarithmic.apply(0.25), 0.5);
+        assert_eq!(VelocityCurve::Compressed.apply(0.0), 0.5);
+    }
+
+    #[test]
+    fn test_shape_velocity() {
+        let row = RowVelocity::default();
+        assert_eq!(
+            shape_velocity(100, None, VelocityCurve::Linear, &row),
+            100.0
+        );
+        assert_eq!(
+            shape_velocity(30, Some(80), VelocityCurve::Linear, &row),
+            80.0
+        );
+
+        let row = RowVelocity {
+            scale: 0.5,
+            offset: 10,
+        };
+        assert_eq!(shape_velocity(127, None, VelocityCurve::Linear, &row), 73.5);
+    }
+}